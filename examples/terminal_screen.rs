@@ -0,0 +1,45 @@
+// Draws a colored diagonal across the snake screen and shows it in the
+// terminal as the program runs
+//
+//  cargo run --example terminal_screen --features terminal
+
+use nes_emu::{terminal, CPU::CPU};
+
+use std::{io, thread, time::Duration};
+
+fn main() -> io::Result<()> {
+    let mut program = Vec::new();
+    for i in 0..32u16 {
+        let addr = terminal::SCREEN_START + i * 33;
+        program.extend_from_slice(&[
+            0xA9, // lda
+            (i % 15 + 1) as u8,
+            0x8D, // sta absolute
+            (addr & 0xFF) as u8,
+            (addr >> 8) as u8,
+        ]);
+    }
+    program.push(0x00); // brk
+
+    let mut cpu = CPU::new();
+    cpu.load(program).expect("program fits in PRG ROM");
+    cpu.reset();
+
+    // clear the terminal once, frames are drawn over each other
+    print!("\x1b[2J");
+
+    let mut stdout = io::stdout();
+    let mut result = Ok(());
+    let mut instructions = 0;
+    cpu.run_with_callback(|cpu| {
+        instructions += 1;
+        // a "frame" every couple of stores
+        if instructions % 4 == 0 && result.is_ok() {
+            result = terminal::render(cpu, &mut stdout);
+            thread::sleep(Duration::from_millis(16));
+        }
+    });
+    result?;
+
+    terminal::render(&cpu, &mut stdout)
+}
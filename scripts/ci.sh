@@ -0,0 +1,15 @@
+#!/bin/sh
+# Everything CI runs, in order. The no_std check lives here rather than in
+# cargo test so the test suite doesn't have to shell out to cargo
+set -eu
+
+cd "$(dirname "$0")/.."
+
+cargo fmt --check
+cargo clippy --workspace --all-targets --all-features -- -D warnings
+cargo test --workspace
+cargo test --workspace --no-default-features
+
+# the core on its own, the way an embedded target would build it
+cargo check --lib --no-default-features
+cargo clippy --lib --no-default-features -- -D warnings
@@ -1,16 +1,49 @@
 mod addressing_modes;
+mod builder;
+mod disassembler;
+mod hang;
+mod history;
+mod illegal_instructions;
+mod instruction;
 mod instructions;
+mod interrupts;
 mod memory;
 mod opcodes;
+pub mod rng;
+mod trace;
 
 #[allow(unused_imports)]
+use crate::CPU::{instructions::*, opcodes::OPCODES_MAP};
+
+pub use crate::CPU::{
+    addressing_modes::AddressingMode,
+    builder::{CpuBuilder, Pattern},
+    hang::HangHandler,
+    instruction::{decode, Instruction},
+    memory::{IoDevice, Mem, RamExecuteHandler, RomWriteHandler},
+    opcodes::{mnemonic, opcodes_for_mnemonic, OpCode, OpCodeCategory},
+    trace::describe_flag_changes,
+};
+
 use crate::CPU::{
-    addressing_modes::AddressingMode, instructions::*, memory::Mem, opcodes::OPCODES_MAP,
+    hang::HangDetector,
+    history::History,
+    memory::{Memory, WrittenMap, ADDRESS_SPACE, INPUT_ADDR, PRG_ROM_START},
+    rng::EmuRng,
 };
 
+use crate::mapper::{Cnrom, Uxrom};
+use crate::rom::{Rom, RomInfo, PRG_BANK_SIZE};
+
 use bitflags::bitflags;
 
-use std::collections::HashMap;
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+use core::{fmt, ops::RangeInclusive};
 
 // Very cool crate!
 bitflags! {
@@ -32,6 +65,7 @@ bitflags! {
     //  | +--------------- Overflow Flag
     //  +----------------- Negative Flag
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct CPUFlags: u8 {
         const CARRY             = 0b00000001;
         const ZERO              = 0b00000010;
@@ -44,8 +78,26 @@ bitflags! {
     }
 }
 
+impl CPUFlags {
+    // The P value as nestest and most trace logs print it: bit 5 always
+    // set and B clear, as neither exists in the register itself
+    pub fn as_nestest_byte(&self) -> u8 {
+        ((*self - CPUFlags::BREAK) | CPUFlags::BREAK2).bits()
+    }
+}
+
+// The stack lives in page one, the stack pointer is an offset into it
+pub const STACK_BASE: u16 = 0x0100;
+// the NMI, reset and IRQ vectors take up the last six bytes
+const VECTORS_START: u16 = 0xFFFA;
+// where the reset sequence reads the entry point from
+const RESET_VECTOR: usize = 0xFFFC;
+// the reset sequence leaves the stack pointer at 0xFD
+const STACK_RESET: u8 = 0xFD;
+
 #[allow(non_snake_case)]
-pub struct CPU {
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU<'a> {
     // the accumulator
     // stores the results of arithmetic, logic, and memory access operations
     // used as an input parameter for some operations
@@ -54,7 +106,8 @@ pub struct CPU {
     // stack pointer
     // memory space [0x0100 .. 0x1FF] is used for stack
     // holds the address of the top of that space
-    // pub stack: u8;
+    // the stack grows downwards, i.e. pushing decrements the pointer
+    pub stack_pointer: u8,
 
     // index register x
     // used as an offset in specific memory addressing modes
@@ -72,12 +125,200 @@ pub struct CPU {
     // holds the address for the next machine language instruction
     pub program_counter: u16,
 
+    // number of CPU cycles spent executing instructions so far
+    pub cycles: usize,
+
     // temporary ram
     // CPU has only 2 KiB of RAM, and everything else is reserved for memory mapping
-    memory: [u8; 0xFFFF],
+    // either owned, or borrowed from the caller with CPU::with_memory
+    memory: Memory<'a>,
     // pub super so that memory trait can be implemented elsewhere
+
+    // when set, reads of 0x00FE return a fresh random byte (see memory.rs)
+    rng: Option<Box<dyn EmuRng>>,
+
+    // devices registered with map_io, consulted before falling back to RAM
+    io: Vec<(RangeInclusive<u16>, Box<dyn IoDevice>)>,
+
+    // strict mode: writes into PRG ROM are reported here and dropped
+    rom_write_handler: Option<RomWriteHandler>,
+
+    // diagnostic for the PC wandering off into RAM, see CpuBuilder
+    ram_execute_handler: Option<RamExecuteHandler>,
+
+    // development aid: RAM that has been written, and what to call when an
+    // opcode is fetched from anywhere else
+    uninitialized_trap: Option<(WrittenMap, RamExecuteHandler)>,
+
+    // watches for instructions that change nothing, see hang.rs
+    hang_detector: Option<HangDetector>,
+
+    // whether ADC honours the decimal flag (false on the NES)
+    decimal_mode_supported: bool,
+
+    // whether bit 5 of the status always reads as set, as on the NES.
+    // Otherwise it's kept like any other flag
+    unused_bit_always_set: bool,
+
+    // whether the loaded cartridge keeps its RAM alive with a battery
+    battery: bool,
+
+    pub state: RunState,
+
+    // set from a callback or hook to make run_with_callback return Halted
+    // before the next instruction, cleared again when it does
+    pub halt_requested: bool,
+
+    // dry run mode: every write, including to devices and the stack, is
+    // silently dropped while reads carry on as normal
+    pub read_only: bool,
+
+    // bounded undo log for step_back, off unless enabled in the builder
+    history: Option<History>,
+
+    // the (address, value) run_until_write is waiting for, and whether
+    // mem_write has seen it yet
+    write_watch: Option<(u16, u8)>,
+    write_watch_hit: bool,
+
+    // instrumentation registered with on_opcode, several hooks per opcode
+    // are fine and run in the order they were added
+    opcode_hooks: Vec<(u8, OpcodeHook)>,
+
+    // replacements for the built-in behaviour of an opcode
+    opcode_handlers: BTreeMap<u8, OpcodeHandler>,
+
+    // opcodes marked with disable_opcode, executing one is an error
+    disabled_opcodes: BTreeSet<u8>,
+
+    // interrupt lines raised with trigger_nmi/trigger_irq
+    nmi_pending: bool,
+    irq_pending: bool,
+
+    // what happened during the current instruction, for OpCode::cycles_for
+    page_crossed: bool,
+    branch_taken: bool,
+
+    // set after a taken branch that stayed on its page, see exec_one
+    delay_interrupt_poll: bool,
+}
+
+// called with the CPU just before an instruction runs, PC on its opcode
+pub type OpcodeHook = Box<dyn FnMut(&mut CPU)>;
+
+// executes an instruction in place of the built-in implementation, with the
+// PC on the operand like the instructions in instructions.rs
+pub type OpcodeHandler = Box<dyn FnMut(&mut CPU, &AddressingMode)>;
+
+// whether the CPU is in the middle of executing or suspended between
+// instructions (e.g. by run_until), in which case it can simply be resumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunState {
+    Running,
+    Paused,
+}
+
+// A hand written starting point for CPU::from_state, e.g. to reproduce a
+// bug report. Defaults to the power-on registers and empty memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub status: CPUFlags,
+    pub stack_pointer: u8,
+    pub program_counter: u16,
+    // (address, bytes) segments, as for CPU::load_segments
+    pub memory: Vec<(u16, Vec<u8>)>,
+}
+
+impl Default for CpuState {
+    fn default() -> Self {
+        CpuState {
+            register_a: 0,
+            register_x: 0,
+            register_y: 0,
+            status: CPUFlags::from_bits_truncate(0b100100),
+            stack_pointer: STACK_RESET,
+            program_counter: 0,
+            memory: Vec::new(),
+        }
+    }
+}
+
+// Why run_with_callback returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    // the program hit BRK or jammed the CPU
+    Stopped,
+    // halt_requested was set
+    Halted,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoadError {
+    // the program doesn't fit between 0x8000 and the NMI/reset/IRQ vectors
+    // at 0xFFFA, or a save doesn't fit in cartridge RAM. Images with their
+    // own vectors can be placed with load_segments instead
+    TooLarge { len: usize, capacity: usize },
+    // a segment would run past the end of the address space
+    OutOfBounds { start: u16, len: usize },
+    // load_rom only knows how to set up these mappers
+    UnsupportedMapper { mapper: u16 },
+    // NROM and CNROM boards carry either 16 or 32 KiB of PRG ROM, bank
+    // switching mappers whole 16 KiB banks
+    PrgSize { len: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::TooLarge { len, capacity } => write!(
+                f,
+                "data is {} bytes but only {} fit in the target region",
+                len, capacity
+            ),
+            LoadError::OutOfBounds { start, len } => write!(
+                f,
+                "segment of {} bytes at {:04X} runs past 0xFFFF",
+                len, start
+            ),
+            LoadError::UnsupportedMapper { mapper } => {
+                write!(f, "mapper {} is not supported", mapper)
+            }
+            LoadError::PrgSize { len } => {
+                write!(
+                    f,
+                    "PRG ROM is {} bytes, which the mapper can't split into banks",
+                    len
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LoadError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExecError {
+    // the opcode at addr was marked with disable_opcode
+    Disabled { code: u8, addr: u16 },
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::Disabled { code, addr } => {
+                write!(f, "opcode {:02X} at {:04X} is disabled", code, addr)
+            }
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for ExecError {}
+
 // CPU works in a constant cycle:
 
 // Fetch next execution instruction from the instruction memory
@@ -85,23 +326,110 @@ pub struct CPU {
 // Execute the Instruction
 // Repeat the cycle
 
-impl Default for CPU {
+impl Default for CPU<'_> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl CPU {
+impl<'a> CPU<'a> {
     pub fn new() -> Self {
+        Self::with_backend(Memory::Owned(vec![0; ADDRESS_SPACE].into_boxed_slice()))
+    }
+
+    // Runs over a caller owned buffer covering the whole address space
+    // instead of allocating one, writes made by the program land in mem
+    pub fn with_memory(mem: &'a mut [u8]) -> Self {
+        assert_eq!(
+            mem.len(),
+            ADDRESS_SPACE,
+            "memory must cover the whole 64 KiB address space"
+        );
+        Self::with_backend(Memory::Borrowed(mem))
+    }
+
+    // A CPU that is ready to continue from state without a reset
+    pub fn from_state(state: CpuState) -> Result<Self, LoadError> {
+        let mut cpu = Self::new();
+        let segments: Vec<(u16, &[u8])> = state
+            .memory
+            .iter()
+            .map(|(start, data)| (*start, data.as_slice()))
+            .collect();
+        cpu.load_segments(&segments)?;
+
+        cpu.register_a = state.register_a;
+        cpu.register_x = state.register_x;
+        cpu.register_y = state.register_y;
+        cpu.status = state.status;
+        cpu.stack_pointer = state.stack_pointer;
+        cpu.program_counter = state.program_counter;
+        Ok(cpu)
+    }
+
+    fn with_backend(memory: Memory<'a>) -> Self {
         CPU {
             register_a: 0,
             register_x: 0,
             register_y: 0,
             status: CPUFlags::from_bits_truncate(0b100100),
             program_counter: 0,
-            memory: [0; 0xFFFF],
+            stack_pointer: STACK_RESET,
+            cycles: 0,
+            memory,
+            rng: None,
+            io: Vec::new(),
+            rom_write_handler: None,
+            ram_execute_handler: None,
+            uninitialized_trap: None,
+            hang_detector: None,
+            decimal_mode_supported: false,
+            unused_bit_always_set: true,
+            battery: false,
+            state: RunState::Paused,
+            halt_requested: false,
+            read_only: false,
+            history: None,
+            write_watch: None,
+            write_watch_hit: false,
+            opcode_hooks: Vec::new(),
+            opcode_handlers: BTreeMap::new(),
+            disabled_opcodes: BTreeSet::new(),
+            nmi_pending: false,
+            irq_pending: false,
+            page_crossed: false,
+            branch_taken: false,
+            delay_interrupt_poll: false,
         }
     }
+}
+
+impl CPU<'_> {
+    // Flag queries
+
+    pub fn carry(&self) -> bool {
+        self.status.contains(CPUFlags::CARRY)
+    }
+
+    pub fn zero(&self) -> bool {
+        self.status.contains(CPUFlags::ZERO)
+    }
+
+    pub fn interrupt_disable(&self) -> bool {
+        self.status.contains(CPUFlags::INTERRUPT_DISABLE)
+    }
+
+    pub fn decimal(&self) -> bool {
+        self.status.contains(CPUFlags::DECIMAL_MODE)
+    }
+
+    pub fn overflow(&self) -> bool {
+        self.status.contains(CPUFlags::OVERFLOW)
+    }
+
+    pub fn negative(&self) -> bool {
+        self.status.contains(CPUFlags::NEGATIV)
+    }
 
     // Device operations
 
@@ -112,77 +440,449 @@ impl CPU {
     pub fn reset(&mut self) {
         self.register_a = 0;
         self.register_x = 0;
+        self.stack_pointer = STACK_RESET;
         self.status = CPUFlags::from_bits_truncate(0b100100);
+        self.status
+            .set(CPUFlags::BREAK2, self.unused_bit_always_set);
+
+        // read like the NMI and IRQ vectors, so a mapper can serve it
+        self.program_counter = self.mem_read_u16(RESET_VECTOR as u16);
+    }
+
+    // The entry point stored at 0xFFFC/0xFFFD, peeked from memory without
+    // going through any device mapped there (reset itself does)
+    pub fn reset_vector(&self) -> u16 {
+        u16::from_le_bytes([self.memory[RESET_VECTOR], self.memory[RESET_VECTOR + 1]])
+    }
+
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.memory[RESET_VECTOR..=RESET_VECTOR + 1].copy_from_slice(&addr.to_le_bytes());
+    }
+
+    // the snake demo polls the last key pressed from 0x00FF
+    pub fn set_input(&mut self, input: u8) {
+        self.mem_write(INPUT_ADDR, input);
+    }
+
+    // Route reads and writes in range to a custom device instead of RAM
+    // if ranges overlap the device mapped first wins
+    pub fn map_io(&mut self, range: RangeInclusive<u16>, handler: Box<dyn IoDevice>) {
+        self.io.push((range, handler));
+    }
+
+    // Call hook every time the opcode code is about to execute
+    pub fn on_opcode(&mut self, code: u8, hook: OpcodeHook) {
+        self.opcode_hooks.push((code, hook));
+    }
+
+    // Execute code with handler instead of the built-in implementation. The
+    // PC is advanced and cycles counted from the opcode table afterwards as
+    // usual; for opcodes not in the table that's up to the handler
+    pub fn set_opcode_handler(&mut self, code: u8, handler: OpcodeHandler) {
+        self.opcode_handlers.insert(code, handler);
+    }
 
-        self.program_counter = self.mem_read_u16(0xFFFC);
+    // Treat code as unsupported, as on a CPU variant without it or to
+    // inject a fault. try_exec_one returns an error when it comes up and
+    // exec_one panics; this takes priority over any handler for the opcode
+    pub fn disable_opcode(&mut self, code: u8) {
+        self.disabled_opcodes.insert(code);
+    }
+
+    fn run_opcode_hooks(&mut self, code: u8) {
+        // taken out for the duration so the hooks can borrow the CPU
+        let mut hooks = core::mem::take(&mut self.opcode_hooks);
+        for (_, hook) in hooks.iter_mut().filter(|(c, _)| *c == code) {
+            hook(self);
+        }
+        // keep any hooks registered from inside a hook
+        hooks.append(&mut self.opcode_hooks);
+        self.opcode_hooks = hooks;
     }
 
+    // Convenience for tests and scripts, panics if the program doesn't fit
     pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
+        self.load(program).expect("program could not be loaded");
         self.reset();
         self.run()
     }
 
-    pub fn load(&mut self, program: Vec<u8>) {
+    // One-liner for scripts: runs program on a fresh CPU and hands the CPU
+    // back for inspection
+    pub fn execute(program: Vec<u8>) -> Self {
+        let mut cpu = Self::new();
+        cpu.load_and_run(program);
+        cpu
+    }
+
+    pub fn load(&mut self, program: Vec<u8>) -> Result<(), LoadError> {
         // [0x8000 .. 0xFFFF] is reserved for Program ROM
-        self.memory[0x8000..(0x8000 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFC, 0x8000);
+        // written directly as this is the cartridge being inserted, not the CPU
+        // the vectors are left alone, load sets the reset vector itself
+        let start = PRG_ROM_START as usize;
+        let capacity = VECTORS_START as usize - start;
+        if program.len() > capacity {
+            return Err(LoadError::TooLarge {
+                len: program.len(),
+                capacity,
+            });
+        }
+
+        self.memory[start..(start + program.len())].copy_from_slice(&program[..]);
+        self.set_reset_vector(PRG_ROM_START);
+        Ok(())
+    }
+
+    // Places each (address, bytes) segment anywhere in memory, e.g. code in
+    // PRG ROM plus data in zero page. Nothing is written unless every segment
+    // fits, and the reset vector is left for the caller to set
+    pub fn load_segments(&mut self, segments: &[(u16, &[u8])]) -> Result<(), LoadError> {
+        if let Some(&(start, data)) = segments
+            .iter()
+            .find(|(start, data)| *start as usize + data.len() > self.memory.len())
+        {
+            return Err(LoadError::OutOfBounds {
+                start,
+                len: data.len(),
+            });
+        }
+
+        for &(start, data) in segments {
+            let start = start as usize;
+            self.memory[start..(start + data.len())].copy_from_slice(data);
+
+            if let Some((written, _)) = self.uninitialized_trap.as_mut() {
+                (start..start + data.len()).for_each(|addr| written.mark(addr as u16));
+            }
+        }
+        Ok(())
+    }
+
+    // Puts a cartridge's PRG ROM in place, vectors included, and returns
+    // what the header said about it. NROM (mapper 0) is copied into memory:
+    // 32 KiB fills 0x8000-0xFFFF and 16 KiB is mirrored at 0xC000. Bank
+    // switching boards are mapped over that range as a device instead
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<RomInfo, LoadError> {
+        let prg = &rom.prg_rom;
+        match rom.mapper {
+            0 | 3 if prg.len() != PRG_BANK_SIZE && prg.len() != 2 * PRG_BANK_SIZE => {
+                return Err(LoadError::PrgSize { len: prg.len() });
+            }
+            0 => {
+                for bank in self.memory[PRG_ROM_START as usize..].chunks_mut(prg.len()) {
+                    bank.copy_from_slice(prg);
+                }
+            }
+            2 => {
+                if prg.is_empty() || !prg.len().is_multiple_of(PRG_BANK_SIZE) {
+                    return Err(LoadError::PrgSize { len: prg.len() });
+                }
+                self.map_io(PRG_ROM_START..=0xFFFF, Box::new(Uxrom::new(rom)));
+            }
+            3 => self.map_io(PRG_ROM_START..=0xFFFF, Box::new(Cnrom::new(rom))),
+            mapper => return Err(LoadError::UnsupportedMapper { mapper }),
+        }
+        self.battery = rom.battery;
+        Ok(rom.info())
     }
 
     pub fn run(&mut self) {
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *OPCODES_MAP;
+        self.run_with_callback(|_| {});
+    }
+
+    // Calls back before every instruction, with the PC on the opcode about
+    // to be executed. Handy for tracing, debuggers and feeding input
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> RunOutcome
+    where
+        F: FnMut(&mut CPU),
+    {
+        self.state = RunState::Running;
+
+        let outcome = loop {
+            callback(self);
+
+            if self.halt_requested {
+                self.halt_requested = false;
+                break RunOutcome::Halted;
+            }
+
+            if !self.exec_one() {
+                break RunOutcome::Stopped;
+            }
+        };
+
+        self.state = RunState::Paused;
+        outcome
+    }
+
+    // For when the callback is expensive (e.g. redrawing a display): it
+    // runs after every nth instruction has finished, the final BRK counting
+    // as one, so a program of total instructions calls it total / n times
+    pub fn run_with_callback_every<F>(&mut self, n: usize, mut callback: F) -> RunOutcome
+    where
+        F: FnMut(&mut CPU),
+    {
+        assert!(n > 0, "callback interval must be at least one instruction");
+        self.state = RunState::Running;
+
+        let mut executed = 0;
+        let outcome = loop {
+            let running = self.exec_one();
+            executed += 1;
+
+            if executed % n == 0 {
+                callback(self);
+
+                if self.halt_requested {
+                    self.halt_requested = false;
+                    break RunOutcome::Halted;
+                }
+            }
+
+            if !running {
+                break RunOutcome::Stopped;
+            }
+        };
+
+        self.state = RunState::Paused;
+        outcome
+    }
+
+    // Executes whole instructions until stop returns true (checked before
+    // each one) or the program hits BRK, then pauses. Calling any of the run
+    // functions again resumes exactly where it left off
+    pub fn run_until<F>(&mut self, stop: F)
+    where
+        F: Fn(&CPU) -> bool,
+    {
+        self.state = RunState::Running;
+
+        while !stop(self) {
+            if !self.exec_one() {
+                break;
+            }
+        }
 
-        loop {
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
+        self.state = RunState::Paused;
+    }
+
+    // Runs for as long as the next instruction still finishes by cycle
+    // target, so the CPU stops on an instruction boundary at or before it
+    // with the pc on the instruction that would have overshot
+    pub fn run_to_cycle(&mut self, target: usize) {
+        self.run_until(|cpu| {
+            cpu.next_instruction_cycles()
+                .is_none_or(|cycles| cpu.cycles + cycles > target)
+        });
+    }
+
+    // How many cycles the next step of exec_one will take given the current
+    // registers and memory, None if the next byte isn't a known opcode
+    pub fn next_instruction_cycles(&self) -> Option<usize> {
+        if self.interrupt_due() {
+            return Some(7);
+        }
+
+        let pc = self.program_counter;
+        let opcode = OpCode::lookup(self.mem_peek(pc))?;
+        let page_crossed = self
+            .peek_effective_address(pc, opcode.mode)
+            .is_some_and(|(_, crossed)| crossed);
+
+        let branch_taken = match decode(opcode.code)? {
+            Instruction::Bcc => !self.carry(),
+            Instruction::Bcs => self.carry(),
+            Instruction::Beq => self.zero(),
+            Instruction::Bmi => self.negative(),
+            Instruction::Bne => !self.zero(),
+            Instruction::Bpl => !self.negative(),
+            Instruction::Bvc => !self.overflow(),
+            Instruction::Bvs => self.overflow(),
+            _ => false,
+        };
+
+        Some(opcode.cycles_for(page_crossed, branch_taken) as usize)
+    }
+
+    // Runs until value is written to addr, the way test ROMs report their
+    // result, or until max_cycles more cycles have gone by or BRK is hit
+    // returns whether the write happened, the CPU is left paused after it
+    pub fn run_until_write(&mut self, addr: u16, value: u8, max_cycles: usize) -> bool {
+        let deadline = self.cycles.saturating_add(max_cycles);
+        self.write_watch = Some((addr, value));
+        self.write_watch_hit = false;
 
-            let opcode = opcodes
-                .get(&code)
-                .expect(&format!("OpCode {:x} is not recognized", code));
+        self.run_until(|cpu| cpu.write_watch_hit || cpu.cycles >= deadline);
 
-            let mode = &opcode.mode;
+        self.write_watch = None;
+        self.write_watch_hit
+    }
 
-            match code {
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => self.adc(mode),
+    // Fetches, decodes and executes a single instruction
+    // returns false once the program hit BRK and should stop
+    pub fn exec_one(&mut self) -> bool {
+        self.try_exec_one().unwrap_or_else(|err| panic!("{}", err))
+    }
 
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => self.and(mode),
+    // exec_one, but a disabled opcode is returned as an error with the pc
+    // left on it instead of panicking
+    pub fn try_exec_one(&mut self) -> Result<bool, ExecError> {
+        if self.hang_detector.is_none() {
+            return self.exec_instruction();
+        }
 
-                0x0A | 0x06 | 0x16 | 0x0E | 0x1E => self.asl(mode),
+        let before = self.registers();
+        let result = self.exec_instruction();
+        self.check_for_hang(before);
+        result
+    }
 
-                // Branching
-                0x90 => self.bcc(),
-                0xB0 => self.bcs(),
-                0xF0 => self.beq(),
-                0x30 => self.bmi(),
-                0xD0 => self.bne(),
-                0x10 => self.bpl(),
-                0x50 => self.bvc(),
-                0x70 => self.bvs(),
+    fn exec_instruction(&mut self) -> Result<bool, ExecError> {
+        let interrupt_due = self.interrupt_due();
 
-                0x24 | 0x2C => self.bit(mode),
+        // checked before anything changes, so the step can simply be retried
+        let code = self.mem_peek(self.program_counter);
+        if !interrupt_due && self.disabled_opcodes.contains(&code) {
+            return Err(ExecError::Disabled {
+                code,
+                addr: self.program_counter,
+            });
+        }
 
-                // Break but wrong
-                0x00 => return,
+        if self.history.is_some() {
+            self.push_snapshot();
+        }
 
-                0x18 => self.clc(),
-                0xD8 => self.cld(),
-                0x58 => self.cli(),
-                0xB8 => self.clv(),
+        // A taken branch that doesn't cross a page polls for interrupts
+        // before its last cycle rather than after it, so one raised while it
+        // ran is only seen after the next instruction
+        self.delay_interrupt_poll = false;
 
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => self.lda(mode),
+        // entering a handler takes a step of its own, so callbacks see the
+        // handler's first instruction like any other
+        if interrupt_due {
+            self.poll_interrupts();
+            return Ok(true);
+        }
 
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => self.sta(mode),
+        if self.program_counter < PRG_ROM_START {
+            if let Some(handler) = self.ram_execute_handler.as_mut() {
+                handler(self.program_counter);
+            }
 
-                0xAA => self.tax(),
-                0xe8 => self.inx(),
-                _ => todo!(),
+            if let Some((written, handler)) = self.uninitialized_trap.as_mut() {
+                if !written.is_written(self.program_counter) {
+                    handler(self.program_counter);
+                }
             }
+        }
+
+        if !self.opcode_hooks.is_empty() {
+            self.run_opcode_hooks(self.mem_peek(self.program_counter));
+        }
+
+        self.page_crossed = false;
+        self.branch_taken = false;
+
+        let code = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        let program_counter_state = self.program_counter;
 
-            // Update the PC accordingly
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.len - 1) as u16;
+        let opcode = OPCODES_MAP[code as usize];
+
+        if let Some(mut handler) = self.opcode_handlers.remove(&code) {
+            let mode = opcode.map_or(AddressingMode::NoneAddressing, |opcode| opcode.mode);
+            handler(self, &mode);
+            // unless the handler replaced itself in the meantime
+            self.opcode_handlers.entry(code).or_insert(handler);
+
+            if let Some(opcode) = opcode {
+                self.finish_instruction(opcode, program_counter_state);
             }
+            return Ok(true);
+        }
+
+        let opcode = opcode.unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+
+        #[cfg(test)]
+        crate::tests::record_opcode(code);
+
+        // every opcode in the table decodes
+        let instruction = decode(code).unwrap();
+
+        match instruction {
+            Instruction::Adc(mode) => self.adc(&mode),
+            Instruction::Sbc(mode) => self.sbc(&mode),
+            Instruction::And(mode) => self.and(&mode),
+            Instruction::Asl(mode) => self.asl(&mode),
+
+            // Branching
+            Instruction::Bcc => self.bcc(),
+            Instruction::Bcs => self.bcs(),
+            Instruction::Beq => self.beq(),
+            Instruction::Bmi => self.bmi(),
+            Instruction::Bne => self.bne(),
+            Instruction::Bpl => self.bpl(),
+            Instruction::Bvc => self.bvc(),
+            Instruction::Bvs => self.bvs(),
+
+            Instruction::Bit(mode) => self.bit(&mode),
+
+            // Break but wrong
+            Instruction::Brk => return Ok(false),
+
+            Instruction::Clc => self.clc(),
+            Instruction::Cld => self.cld(),
+            Instruction::Cli => self.cli(),
+            Instruction::Clv => self.clv(),
+
+            Instruction::Sec => self.sec(),
+            Instruction::Sed => self.sed(),
+
+            Instruction::Jmp => self.jmp(),
+            Instruction::Jsr => self.jsr(),
+
+            Instruction::Lda(mode) => self.lda(&mode),
+
+            Instruction::Pha => self.pha(),
+            Instruction::Php => self.php(),
+            Instruction::Pla => self.pla(),
+            Instruction::Plp => self.plp(),
+
+            Instruction::Rti => self.rti(),
+            Instruction::Rts => self.rts(),
+
+            Instruction::Sta(mode) => self.sta(&mode),
+
+            Instruction::Tax => self.tax(),
+            Instruction::Inx => self.inx(),
+
+            Instruction::Nop => {}
+
+            // Illegal opcodes
+            Instruction::Lax(mode) => self.lax(&mode),
+            Instruction::Slo(mode) => self.slo(&mode),
+            Instruction::Rla(mode) => self.rla(&mode),
+            Instruction::Sre(mode) => self.sre(&mode),
+            Instruction::Rra(mode) => self.rra(&mode),
+
+            Instruction::Jam => return Ok(false),
+        }
+
+        self.finish_instruction(opcode, program_counter_state);
+        Ok(true)
+    }
+
+    // program_counter_state is the PC right after the opcode was fetched
+    fn finish_instruction(&mut self, opcode: &OpCode, program_counter_state: u16) {
+        // JSR, RTS and RTI have internal stack operations that the table
+        // counts already take into account (6 cycles each)
+        self.cycles += opcode.cycles_for(self.page_crossed, self.branch_taken) as usize;
+        self.delay_interrupt_poll = self.branch_taken && !self.page_crossed;
+
+        // Update the PC accordingly, unless the instruction jumped
+        if program_counter_state == self.program_counter {
+            self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
         }
     }
 }
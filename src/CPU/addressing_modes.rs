@@ -1,4 +1,4 @@
-use crate::CPU::{Mem, CPU};
+use crate::CPU::{Mem, OpCode, CPU};
 
 // The NES was nice enough to use different addressing modes
 // i.e different ways to get a parameter for an instruction
@@ -12,7 +12,7 @@ use crate::CPU::{Mem, CPU};
 // CPU instruction size can be either 1, 2, or 3 bytes.
 // no opcodes that occupy more than 3 bytes
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
 pub enum AddressingMode {
@@ -53,61 +53,146 @@ pub enum AddressingMode {
     Indirect_X,
     Indirect_Y,
 
+    // used by branches: a signed 8-bit offset from the address of the next
+    // instruction. The branch instructions read it themselves
+    Relative,
+
     // none
     NoneAddressing,
 }
 
-impl CPU {
+impl CPU<'_> {
     pub(super) fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
         match mode {
             AddressingMode::Immediate => self.program_counter,
 
             AddressingMode::ZeroPage => self.mem_read(self.program_counter).into(),
 
-            AddressingMode::ZeroPage_X => {
-                let zero_page_addr = self.mem_read(self.program_counter);
-                zero_page_addr.wrapping_add(self.register_x) as u16
-            }
-
-            AddressingMode::ZeroPage_Y => {
-                let zero_page_addr = self.mem_read(self.program_counter);
-                zero_page_addr.wrapping_add(self.register_y) as u16
-            }
+            AddressingMode::ZeroPage_X => self.index_zero_page(self.register_x),
+            AddressingMode::ZeroPage_Y => self.index_zero_page(self.register_y),
 
             AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
 
-            AddressingMode::Absolute_X => {
-                let abs_addr = self.mem_read_u16(self.program_counter);
-                abs_addr.wrapping_add(self.register_x as u16)
-            }
-
-            AddressingMode::Absolute_Y => {
-                let abs_addr = self.mem_read_u16(self.program_counter);
-                abs_addr.wrapping_add(self.register_y as u16)
-            }
+            AddressingMode::Absolute_X => self.index_absolute(self.register_x, false),
+            AddressingMode::Absolute_Y => self.index_absolute(self.register_y, false),
 
             AddressingMode::Indirect_X => {
                 let base = self.mem_read(self.program_counter);
-
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-                // note: we have to do this due to little endian
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
+                self.read_zp_pointer(base.wrapping_add(self.register_x))
             }
 
             AddressingMode::Indirect_Y => {
                 let base = self.mem_read(self.program_counter);
-
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-                deref_base.wrapping_add(self.register_y as u16)
+                let deref_base = self.read_zp_pointer(base);
+                let addr = deref_base.wrapping_add(self.register_y as u16);
+                self.page_crossed = deref_base & 0xFF00 != addr & 0xFF00;
+                addr
             }
 
-            AddressingMode::NoneAddressing => {
+            AddressingMode::Relative | AddressingMode::NoneAddressing => {
                 panic!("Invalid addressing mode! Mode: {:?} is not supported", mode)
             }
         }
     }
+
+    // Stores and read-modify-write instructions always spend the cycle that
+    // fixes up the high byte, so their dummy read happens crossed or not
+    pub(super) fn get_write_address(&mut self, mode: &AddressingMode) -> u16 {
+        match mode {
+            AddressingMode::Absolute_X => self.index_absolute(self.register_x, true),
+            AddressingMode::Absolute_Y => self.index_absolute(self.register_y, true),
+            _ => self.get_operand_address(mode),
+        }
+    }
+
+    // The effective address of the instruction at pc and the value there,
+    // using the current registers, without executing or touching devices
+    // (the value comes from mem_peek). None for modes without an operand
+    pub fn preview_operand(&self, pc: u16) -> Option<(u16, u8)> {
+        let opcode = OpCode::lookup(self.mem_peek(pc))?;
+        if opcode.mode == AddressingMode::Relative {
+            return None;
+        }
+
+        let (addr, _) = self.peek_effective_address(pc, opcode.mode)?;
+        Some((addr, self.mem_peek(addr)))
+    }
+
+    // What get_operand_address would return for an instruction in mode at
+    // pc, plus whether indexing crossed a page, but read with mem_peek so no
+    // device sees it. Relative gives the branch target. None without operand
+    pub(super) fn peek_effective_address(
+        &self,
+        pc: u16,
+        mode: AddressingMode,
+    ) -> Option<(u16, bool)> {
+        let operand = pc.wrapping_add(1);
+        let arg = self.mem_peek(operand);
+        let peek_u16 =
+            |lo: u16, hi: u16| u16::from_le_bytes([self.mem_peek(lo), self.mem_peek(hi)]);
+        let indexed = |base: u16, index: u8| {
+            let addr = base.wrapping_add(index as u16);
+            (addr, base & 0xFF00 != addr & 0xFF00)
+        };
+
+        let address = match mode {
+            AddressingMode::Immediate => (operand, false),
+            AddressingMode::ZeroPage => (arg as u16, false),
+            AddressingMode::ZeroPage_X => (arg.wrapping_add(self.register_x) as u16, false),
+            AddressingMode::ZeroPage_Y => (arg.wrapping_add(self.register_y) as u16, false),
+            AddressingMode::Absolute => (peek_u16(operand, operand.wrapping_add(1)), false),
+            AddressingMode::Absolute_X => {
+                indexed(peek_u16(operand, operand.wrapping_add(1)), self.register_x)
+            }
+            AddressingMode::Absolute_Y => {
+                indexed(peek_u16(operand, operand.wrapping_add(1)), self.register_y)
+            }
+            AddressingMode::Indirect_X => {
+                let ptr = arg.wrapping_add(self.register_x);
+                (peek_u16(ptr as u16, ptr.wrapping_add(1) as u16), false)
+            }
+            AddressingMode::Indirect_Y => indexed(
+                peek_u16(arg as u16, arg.wrapping_add(1) as u16),
+                self.register_y,
+            ),
+            AddressingMode::Relative => {
+                let next = pc.wrapping_add(2);
+                let target = next.wrapping_add(arg as i8 as u16);
+                (target, next & 0xFF00 != target & 0xFF00)
+            }
+            AddressingMode::NoneAddressing => return None,
+        };
+
+        Some(address)
+    }
+
+    // Reads a little endian pointer stored in the zero page
+    // the high byte of a pointer at 0xFF comes from 0x00, not 0x100
+    pub(crate) fn read_zp_pointer(&mut self, zp_addr: u8) -> u16 {
+        let lo = self.mem_read(zp_addr as u16);
+        let hi = self.mem_read(zp_addr.wrapping_add(1) as u16);
+        (hi as u16) << 8 | (lo as u16)
+    }
+
+    // Indexed zero page addresses wrap around within the zero page
+    fn index_zero_page(&mut self, index: u8) -> u16 {
+        let zero_page_addr = self.mem_read(self.program_counter);
+        zero_page_addr.wrapping_add(index) as u16
+    }
+
+    // The 6502 adds the index to the low byte of the address first and reads
+    // from that (possibly wrong) address while it fixes up the high byte.
+    // Reads skip that step when no page is crossed, writes always do it.
+    // The dummy read matters for registers with read side effects
+    fn index_absolute(&mut self, index: u8, always_dummy_read: bool) -> u16 {
+        let abs_addr = self.mem_read_u16(self.program_counter);
+        let addr = abs_addr.wrapping_add(index as u16);
+
+        self.page_crossed = abs_addr & 0xFF00 != addr & 0xFF00;
+        if self.page_crossed || always_dummy_read {
+            self.mem_read((abs_addr & 0xFF00) | (addr & 0x00FF));
+        }
+
+        addr
+    }
 }
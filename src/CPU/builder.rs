@@ -0,0 +1,142 @@
+use crate::CPU::{
+    hang::{HangDetector, HangHandler},
+    history::History,
+    memory::{WrittenMap, INTERNAL_RAM_SIZE},
+    rng::{EmuRng, Lcg},
+    CPUFlags, RamExecuteHandler, RomWriteHandler, CPU,
+};
+
+use alloc::boxed::Box;
+
+// What the 2 KiB of internal RAM holds before the program touches it
+// real consoles power up with a mostly-but-not-quite regular pattern
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    #[default]
+    AllZero,
+    AllFF,
+    // 0x00 at even addresses and 0xFF at odd ones
+    Alternating,
+    // drawn from the CPU's RNG, or a default seeded one if it has none
+    Random,
+}
+
+impl Pattern {
+    fn byte_at(self, addr: usize, rng: &mut dyn EmuRng) -> u8 {
+        match self {
+            Pattern::AllZero => 0x00,
+            Pattern::AllFF => 0xFF,
+            Pattern::Alternating if addr & 1 == 0 => 0x00,
+            Pattern::Alternating => 0xFF,
+            Pattern::Random => rng.next_u8(),
+        }
+    }
+}
+
+// Optional configuration for a CPU
+// CPU::new() is still the way to get a plain CPU with flat memory
+#[derive(Default)]
+pub struct CpuBuilder {
+    rng: Option<Box<dyn EmuRng>>,
+    rom_write_handler: Option<RomWriteHandler>,
+    ram_execute_handler: Option<RamExecuteHandler>,
+    uninitialized_execute_handler: Option<RamExecuteHandler>,
+    hang_detector: Option<HangDetector>,
+    decimal_mode_supported: bool,
+    unused_bit_free: bool,
+    history_depth: Option<usize>,
+    power_on_pattern: Pattern,
+}
+
+impl CpuBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Maps the random number register at 0x00FE to the given RNG
+    pub fn rng(mut self, rng: Box<dyn EmuRng>) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    // Strict mode: PRG ROM can't be written on NROM, so instead of landing in
+    // memory such writes are dropped and reported to the handler
+    pub fn strict_rom(mut self, handler: RomWriteHandler) -> Self {
+        self.rom_write_handler = Some(handler);
+        self
+    }
+
+    // Running code from RAM is legal but usually means the PC ran off into
+    // data. The handler gets the PC of every opcode fetched below 0x8000
+    pub fn on_ram_execute(mut self, handler: RamExecuteHandler) -> Self {
+        self.ram_execute_handler = Some(handler);
+        self
+    }
+
+    // Catches wild jumps: the handler gets the PC of every opcode fetched
+    // from RAM that was never written, by the program or by load_segments
+    pub fn trap_uninitialized_execute(mut self, handler: RamExecuteHandler) -> Self {
+        self.uninitialized_execute_handler = Some(handler);
+        self
+    }
+
+    // Reports a likely hang: threshold instructions in a row that left the
+    // registers and memory untouched, as a JMP to itself does
+    pub fn detect_hangs(mut self, threshold: usize, handler: HangHandler) -> Self {
+        self.hang_detector = Some(HangDetector::new(threshold, handler));
+        self
+    }
+
+    // The NES 2A03 has the BCD circuitry cut, so by default ADC ignores the
+    // decimal flag. Turn this on to emulate a generic 6502
+    pub fn decimal_mode_supported(mut self, supported: bool) -> Self {
+        self.decimal_mode_supported = supported;
+        self
+    }
+
+    // Bit 5 of the status register always reads as 1 on the NES. Pass false
+    // for 6502-family chips where it's stored and pulled like any other bit
+    pub fn unused_bit_always_set(mut self, set: bool) -> Self {
+        self.unused_bit_free = !set;
+        self
+    }
+
+    // Remember the last depth instructions so they can be undone with
+    // CPU::step_back
+    pub fn history(mut self, depth: usize) -> Self {
+        self.history_depth = Some(depth);
+        self
+    }
+
+    // Fill internal RAM with pattern instead of zeroes, e.g. to check a
+    // program doesn't rely on uninitialised memory
+    pub fn power_on_pattern(mut self, pattern: Pattern) -> Self {
+        self.power_on_pattern = pattern;
+        self
+    }
+
+    pub fn build(self) -> CPU<'static> {
+        let mut cpu = CPU::new();
+        cpu.rng = self.rng;
+        cpu.rom_write_handler = self.rom_write_handler;
+        cpu.ram_execute_handler = self.ram_execute_handler;
+        cpu.uninitialized_trap = self
+            .uninitialized_execute_handler
+            .map(|handler| (WrittenMap::new(), handler));
+        cpu.hang_detector = self.hang_detector;
+        cpu.decimal_mode_supported = self.decimal_mode_supported;
+        cpu.unused_bit_always_set = !self.unused_bit_free;
+        cpu.status.set(CPUFlags::BREAK2, cpu.unused_bit_always_set);
+        cpu.history = self.history_depth.map(History::new);
+
+        let mut fallback = Lcg::default();
+        let rng: &mut dyn EmuRng = match cpu.rng.as_mut() {
+            Some(rng) => rng.as_mut(),
+            None => &mut fallback,
+        };
+        for (addr, byte) in cpu.memory[..INTERNAL_RAM_SIZE].iter_mut().enumerate() {
+            *byte = self.power_on_pattern.byte_at(addr, rng);
+        }
+        cpu
+    }
+}
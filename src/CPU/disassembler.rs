@@ -0,0 +1,109 @@
+use crate::CPU::{AddressingMode, OpCode, CPU};
+
+use core::iter;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+impl CPU<'_> {
+    // Renders the instruction at addr in the usual 6502 assembly syntax
+    // e.g. LDA #$05, STA $0200,X, BNE $8010
+    // returns None if the byte at addr is not a known opcode
+    pub fn disassemble(&self, addr: u16) -> Option<String> {
+        let opcode = OpCode::lookup(self.mem_peek(addr))?;
+
+        let arg = self.mem_peek(addr.wrapping_add(1));
+        let arg_u16 = (self.mem_peek(addr.wrapping_add(2)) as u16) << 8 | arg as u16;
+
+        let operand = match opcode.mode {
+            AddressingMode::Immediate => format!("#${:02X}", arg),
+            AddressingMode::ZeroPage => format!("${:02X}", arg),
+            AddressingMode::ZeroPage_X => format!("${:02X},X", arg),
+            AddressingMode::ZeroPage_Y => format!("${:02X},Y", arg),
+            AddressingMode::Absolute => format!("${:04X}", arg_u16),
+            AddressingMode::Absolute_X => format!("${:04X},X", arg_u16),
+            AddressingMode::Absolute_Y => format!("${:04X},Y", arg_u16),
+            AddressingMode::Indirect_X => format!("(${:02X},X)", arg),
+            AddressingMode::Indirect_Y => format!("(${:02X}),Y", arg),
+            AddressingMode::Relative => {
+                // the offset is signed and relative to the next instruction
+                let target = addr
+                    .wrapping_add(opcode.len as u16)
+                    .wrapping_add(arg as i8 as u16);
+                format!("${:04X}", target)
+            }
+            AddressingMode::NoneAddressing => return Some(opcode.mnemonic.to_string()),
+        };
+
+        Some(format!("{} {}", opcode.mnemonic, operand))
+    }
+
+    // The instruction about to execute and its address
+    // meant to be used from a run_with_callback closure
+    pub fn current_instruction(&self) -> Option<(u16, String)> {
+        let instruction = self.disassemble(self.program_counter)?;
+        Some((self.program_counter, instruction))
+    }
+
+    // Addressing mode of the instruction about to execute, e.g. for
+    // highlighting its operand
+    pub fn mode_at_pc(&self) -> Option<&'static AddressingMode> {
+        OpCode::lookup(self.mem_peek(self.program_counter)).map(|opcode| &opcode.mode)
+    }
+
+    // Lazily disassembles consecutive instructions starting at start, e.g.
+    // cpu.instructions(cpu.program_counter).take(20) for a debugger window
+    // bytes that aren't opcodes come out as data, and it stops at 0xFFFF
+    pub fn instructions(&self, start: u16) -> impl Iterator<Item = (u16, String)> + '_ {
+        let mut next = Some(start);
+
+        iter::from_fn(move || {
+            let addr = next?;
+            let (text, len) = match self.disassemble(addr) {
+                Some(text) => (text, OpCode::lookup(self.mem_peek(addr))?.len),
+                None => (format!(".byte ${:02X}", self.mem_peek(addr)), 1),
+            };
+
+            next = addr.checked_add(len as u16);
+            Some((addr, text))
+        })
+    }
+
+    // The raw bytes of the instruction at addr as hex, e.g. "8D 00 02"
+    // a byte that isn't an opcode is shown on its own
+    pub fn instruction_bytes(&self, addr: u16) -> String {
+        let len = OpCode::lookup(self.mem_peek(addr)).map_or(1, |opcode| opcode.len);
+        let bytes: Vec<String> = (0..len as u16)
+            .map(|i| format!("{:02X}", self.mem_peek(addr.wrapping_add(i))))
+            .collect();
+        bytes.join(" ")
+    }
+
+    // Writes an assembly listing of every instruction starting in
+    // [start .. end], one per line:
+    // 8000  A9 05     LDA #$05
+    #[cfg(feature = "std")]
+    pub fn export_listing<W: std::io::Write>(
+        &self,
+        start: u16,
+        end: u16,
+        w: &mut W,
+    ) -> std::io::Result<()> {
+        for (addr, text) in self
+            .instructions(start)
+            .take_while(|(addr, _)| *addr <= end)
+        {
+            writeln!(
+                w,
+                "{:04X}  {:<8}  {}",
+                addr,
+                self.instruction_bytes(addr),
+                text
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use crate::CPU::{CPUFlags, CPU};
+
+use alloc::boxed::Box;
+
+// Hang detection
+//
+// A wait loop like `JMP self` is normal while a game waits for NMI, but the
+// same loop with interrupts never coming is usually a bug. An instruction
+// that leaves the PC and every register where they were and writes nothing
+// did nothing at all; after threshold of those in a row the handler is told
+// the PC it's stuck at. Loops spanning several instructions aren't caught
+
+// called with the pc of the stuck instruction
+pub type HangHandler = Box<dyn FnMut(u16)>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) struct Registers {
+    register_a: u8,
+    register_x: u8,
+    register_y: u8,
+    stack_pointer: u8,
+    status: CPUFlags,
+    program_counter: u16,
+}
+
+pub(super) struct HangDetector {
+    threshold: usize,
+    handler: HangHandler,
+    // instructions in a row that changed nothing
+    idle: usize,
+    // set by mem_write during the current instruction
+    pub(super) wrote: bool,
+}
+
+impl HangDetector {
+    pub(super) fn new(threshold: usize, handler: HangHandler) -> Self {
+        HangDetector {
+            threshold,
+            handler,
+            idle: 0,
+            wrote: false,
+        }
+    }
+}
+
+impl CPU<'_> {
+    pub(super) fn registers(&self) -> Registers {
+        Registers {
+            register_a: self.register_a,
+            register_x: self.register_x,
+            register_y: self.register_y,
+            stack_pointer: self.stack_pointer,
+            status: self.status,
+            program_counter: self.program_counter,
+        }
+    }
+
+    // before is what registers() returned ahead of the instruction that just
+    // ran. The handler fires once per hang, when the threshold is reached
+    pub(super) fn check_for_hang(&mut self, before: Registers) {
+        let unchanged = self.registers() == before;
+        let Some(detector) = self.hang_detector.as_mut() else {
+            return;
+        };
+
+        if unchanged && !detector.wrote {
+            detector.idle += 1;
+            if detector.idle == detector.threshold {
+                (detector.handler)(before.program_counter);
+            }
+        } else {
+            detector.idle = 0;
+        }
+        detector.wrote = false;
+    }
+}
@@ -0,0 +1,99 @@
+use crate::CPU::{CPUFlags, CPU};
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+// Reverse stepping
+//
+// When enabled, every instruction records the registers it started from and
+// the old value of every byte it writes, so it can be undone without
+// snapshotting all 64KB. Only the last few instructions are kept.
+// Writes to mapped devices and the random register can't be undone
+
+pub(super) struct Snapshot {
+    register_a: u8,
+    register_x: u8,
+    register_y: u8,
+    stack_pointer: u8,
+    status: CPUFlags,
+    program_counter: u16,
+    cycles: usize,
+    // an interrupt entered by the step is pending again once it's undone
+    nmi_pending: bool,
+    irq_pending: bool,
+    delay_interrupt_poll: bool,
+    // (address, value before the write), in the order they happened
+    writes: Vec<(u16, u8)>,
+}
+
+pub(super) struct History {
+    depth: usize,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl History {
+    pub(super) fn new(depth: usize) -> Self {
+        History {
+            depth,
+            snapshots: VecDeque::with_capacity(depth),
+        }
+    }
+
+    // remembers that addr held old before the current instruction wrote it
+    pub(super) fn record_write(&mut self, addr: u16, old: u8) {
+        if let Some(snapshot) = self.snapshots.back_mut() {
+            snapshot.writes.push((addr, old));
+        }
+    }
+}
+
+impl CPU<'_> {
+    pub(super) fn push_snapshot(&mut self) {
+        let snapshot = Snapshot {
+            register_a: self.register_a,
+            register_x: self.register_x,
+            register_y: self.register_y,
+            stack_pointer: self.stack_pointer,
+            status: self.status,
+            program_counter: self.program_counter,
+            cycles: self.cycles,
+            nmi_pending: self.nmi_pending,
+            irq_pending: self.irq_pending,
+            delay_interrupt_poll: self.delay_interrupt_poll,
+            writes: Vec::new(),
+        };
+
+        if let Some(history) = self.history.as_mut() {
+            if history.depth == 0 {
+                return;
+            }
+            if history.snapshots.len() == history.depth {
+                history.snapshots.pop_front();
+            }
+            history.snapshots.push_back(snapshot);
+        }
+    }
+
+    // Undoes the last executed instruction
+    // returns false if history is off or there is nothing left to undo
+    pub fn step_back(&mut self) -> bool {
+        let Some(snapshot) = self.history.as_mut().and_then(|h| h.snapshots.pop_back()) else {
+            return false;
+        };
+
+        for &(addr, old) in snapshot.writes.iter().rev() {
+            self.memory[addr as usize] = old;
+        }
+
+        self.register_a = snapshot.register_a;
+        self.register_x = snapshot.register_x;
+        self.register_y = snapshot.register_y;
+        self.stack_pointer = snapshot.stack_pointer;
+        self.status = snapshot.status;
+        self.program_counter = snapshot.program_counter;
+        self.cycles = snapshot.cycles;
+        self.nmi_pending = snapshot.nmi_pending;
+        self.irq_pending = snapshot.irq_pending;
+        self.delay_interrupt_poll = snapshot.delay_interrupt_poll;
+        true
+    }
+}
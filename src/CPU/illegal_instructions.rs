@@ -0,0 +1,47 @@
+use crate::CPU::{AddressingMode, Mem, CPU};
+
+// Undocumented opcodes
+// these fall out of how the 6502 decodes instructions, often running two
+// official instructions at once. Some games and test ROMs rely on them
+
+impl CPU<'_> {
+    // LDA and LDX at once: loads a byte of memory into A and X
+    pub(super) fn lax(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+
+        self.register_a = value;
+        self.register_x = value;
+        self.update_zero_and_negative_flags(value);
+    }
+
+    // The read-modify-write combos: shift or rotate a byte of memory, write
+    // it back, then combine the result with the accumulator
+
+    // ASL then ORA
+    pub(super) fn slo(&mut self, mode: &AddressingMode) {
+        let res = self.read_modify_write(mode, Self::bit_shift_left_and_set_flags);
+        self.register_a |= res;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    // ROL then AND
+    pub(super) fn rla(&mut self, mode: &AddressingMode) {
+        let res = self.read_modify_write(mode, Self::rotate_left_and_set_flags);
+        self.register_a &= res;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    // LSR then EOR
+    pub(super) fn sre(&mut self, mode: &AddressingMode) {
+        let res = self.read_modify_write(mode, Self::bit_shift_right_and_set_flags);
+        self.register_a ^= res;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    // ROR then ADC, the carry out of the rotate goes into the addition
+    pub(super) fn rra(&mut self, mode: &AddressingMode) {
+        let res = self.read_modify_write(mode, Self::rotate_right_and_set_flags);
+        self.add_with_carry(res);
+    }
+}
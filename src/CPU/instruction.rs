@@ -0,0 +1,123 @@
+use crate::CPU::{AddressingMode, OpCode};
+
+// What an opcode byte does, with its addressing mode where it has a choice
+// of them. The opcode table still has the length and timing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Adc(AddressingMode),
+    And(AddressingMode),
+    // NoneAddressing shifts the accumulator
+    Asl(AddressingMode),
+    Bcc,
+    Bcs,
+    Beq,
+    Bit(AddressingMode),
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Inx,
+    Jmp,
+    Jsr,
+    Lda(AddressingMode),
+    Nop,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rti,
+    Rts,
+    Sbc(AddressingMode),
+    Sec,
+    Sed,
+    Sta(AddressingMode),
+    Tax,
+
+    // Illegal opcodes
+    Lax(AddressingMode),
+    Rla(AddressingMode),
+    Rra(AddressingMode),
+    Slo(AddressingMode),
+    Sre(AddressingMode),
+    // the CPU locks up, only a reset gets it going again
+    Jam,
+}
+
+// None for bytes that aren't in the opcode table
+pub fn decode(code: u8) -> Option<Instruction> {
+    let mode = OpCode::lookup(code)?.mode;
+
+    let instruction = match code {
+        0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => Instruction::Adc(mode),
+
+        0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => Instruction::Sbc(mode),
+
+        0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => Instruction::And(mode),
+
+        0x0A | 0x06 | 0x16 | 0x0E | 0x1E => Instruction::Asl(mode),
+
+        // Branching
+        0x90 => Instruction::Bcc,
+        0xB0 => Instruction::Bcs,
+        0xF0 => Instruction::Beq,
+        0x30 => Instruction::Bmi,
+        0xD0 => Instruction::Bne,
+        0x10 => Instruction::Bpl,
+        0x50 => Instruction::Bvc,
+        0x70 => Instruction::Bvs,
+
+        0x24 | 0x2C => Instruction::Bit(mode),
+
+        0x00 => Instruction::Brk,
+
+        0x18 => Instruction::Clc,
+        0xD8 => Instruction::Cld,
+        0x58 => Instruction::Cli,
+        0xB8 => Instruction::Clv,
+
+        0x38 => Instruction::Sec,
+        0xF8 => Instruction::Sed,
+
+        0x4C => Instruction::Jmp,
+
+        0x20 => Instruction::Jsr,
+
+        0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => Instruction::Lda(mode),
+
+        0x48 => Instruction::Pha,
+        0x08 => Instruction::Php,
+        0x68 => Instruction::Pla,
+        0x28 => Instruction::Plp,
+
+        0x40 => Instruction::Rti,
+        0x60 => Instruction::Rts,
+
+        0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => Instruction::Sta(mode),
+
+        0xAA => Instruction::Tax,
+        0xe8 => Instruction::Inx,
+
+        0xEA => Instruction::Nop,
+
+        // Illegal opcodes
+        0xA7 | 0xB7 | 0xAF | 0xBF | 0xA3 | 0xB3 => Instruction::Lax(mode),
+        0x07 | 0x17 | 0x0F | 0x1F | 0x1B | 0x03 | 0x13 => Instruction::Slo(mode),
+        0x27 | 0x37 | 0x2F | 0x3F | 0x3B | 0x23 | 0x33 => Instruction::Rla(mode),
+        0x47 | 0x57 | 0x4F | 0x5F | 0x5B | 0x43 | 0x53 => Instruction::Sre(mode),
+        0x67 | 0x77 | 0x6F | 0x7F | 0x7B | 0x63 | 0x73 => Instruction::Rra(mode),
+
+        0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+            Instruction::Jam
+        }
+
+        _ => unreachable!("OpCode {:02x} is in the table but can't be decoded", code),
+    };
+
+    Some(instruction)
+}
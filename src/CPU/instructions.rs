@@ -2,13 +2,13 @@ use crate::CPU::AddressingMode;
 use crate::CPU::CPUFlags;
 use crate::CPU::Mem;
 use crate::CPU::CPU;
+use crate::CPU::STACK_BASE;
 
 #[allow(dead_code)]
-
-impl CPU {
+impl CPU<'_> {
     // Command Helpers
 
-    fn update_zero_and_negative_flags(&mut self, result: u8) {
+    pub(super) fn update_zero_and_negative_flags(&mut self, result: u8) {
         // If 0 then set zero
         self.status.set(CPUFlags::ZERO, result == 0);
 
@@ -66,6 +66,83 @@ impl CPU {
         self.register_a = res;
     }
 
+    // address of the top of the stack
+    // the high byte is always 0x01, the pointer wraps within the page
+    fn stack_addr(&self) -> u16 {
+        STACK_BASE | self.stack_pointer as u16
+    }
+
+    pub(super) fn stack_push(&mut self, data: u8) {
+        self.mem_write(self.stack_addr(), data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+
+    // the pointer sits on the next free slot, so it is moved back onto the
+    // last pushed byte before reading
+    pub(super) fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(self.stack_addr())
+    }
+
+    // high byte goes first so the value ends up little endian in memory
+    pub(super) fn stack_push_u16(&mut self, data: u16) {
+        self.stack_push((data >> 8) as u8);
+        self.stack_push((data & 0xff) as u8);
+    }
+
+    pub(super) fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        hi << 8 | lo
+    }
+
+    // Binary coded decimal addition, each nibble holds a digit 0-9
+    // follows the NMOS 6502: Z comes from the binary sum while N and V are
+    // taken from the result before the high digit is adjusted
+    fn add_to_reg_a_decimal(&mut self, arg: u8) {
+        let a = self.register_a;
+        let carry = self.status.contains(CPUFlags::CARRY) as u8;
+        let binary = a.wrapping_add(arg).wrapping_add(carry);
+
+        let mut lo = (a & 0x0F) + (arg & 0x0F) + carry;
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+        let mut hi = (a >> 4) + (arg >> 4) + (lo > 0x0F) as u8;
+
+        let intermediate = (hi << 4) | (lo & 0x0F);
+        self.status.set(CPUFlags::NEGATIV, intermediate >> 7 == 1);
+        self.update_overflow_flag(a, arg, intermediate);
+
+        if hi > 0x09 {
+            hi += 0x06;
+        }
+        self.status.set(CPUFlags::CARRY, hi > 0x0F);
+        self.status.set(CPUFlags::ZERO, binary == 0);
+
+        self.register_a = (hi << 4) | (lo & 0x0F);
+    }
+
+    // Binary coded decimal subtraction. On the NMOS 6502 all the flags are
+    // the ones the binary subtraction would produce, only A is adjusted
+    fn sub_from_reg_a_decimal(&mut self, arg: u8) {
+        let a = self.register_a;
+        let borrow = !self.status.contains(CPUFlags::CARRY) as i16;
+
+        let mut lo = (a & 0x0F) as i16 - (arg & 0x0F) as i16 - borrow;
+        let mut hi = (a >> 4) as i16 - (arg >> 4) as i16;
+        if lo < 0 {
+            lo -= 0x06;
+            hi -= 1;
+        }
+        if hi < 0 {
+            hi -= 0x06;
+        }
+
+        self.add_to_reg_a(!arg);
+        self.register_a = ((hi << 4) | (lo & 0x0F)) as u8;
+    }
+
     // Commands
 
     // Adds the contents of a memory location to the accumulator together with the carry bit.
@@ -73,7 +150,32 @@ impl CPU {
     pub(super) fn adc(&mut self, mode: &AddressingMode) {
         let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
-        self.add_to_reg_a(value);
+        self.add_with_carry(value);
+    }
+
+    // ADC's arithmetic, shared with RRA
+    pub(super) fn add_with_carry(&mut self, value: u8) {
+        if self.decimal_mode_supported && self.status.contains(CPUFlags::DECIMAL_MODE) {
+            self.add_to_reg_a_decimal(value);
+        } else {
+            self.add_to_reg_a(value);
+        }
+    }
+
+    // Subtracts the contents of a memory location from the accumulator together
+    // with the not of the carry bit. The carry is a borrow in reverse: it has
+    // to be set before the first byte of a subtraction and ends up clear if
+    // the result went below zero
+    pub(super) fn sbc(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+
+        if self.decimal_mode_supported && self.status.contains(CPUFlags::DECIMAL_MODE) {
+            self.sub_from_reg_a_decimal(value);
+        } else {
+            // A - M - (1 - C) == A + !M + C, the flags come out right too
+            self.add_to_reg_a(!value);
+        }
     }
 
     // logical AND on the accumulator contents using the contents of a byte of memory
@@ -81,11 +183,11 @@ impl CPU {
         let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
 
-        self.register_a = value & self.register_a;
+        self.register_a &= value;
         self.update_zero_and_negative_flags(self.register_a);
     }
 
-    fn bit_shift_left_and_set_flags(&mut self, value: u8) -> u8 {
+    pub(super) fn bit_shift_left_and_set_flags(&mut self, value: u8) -> u8 {
         // Set carry flag
         self.status.set(CPUFlags::CARRY, value >> 7 == 1);
         let res = value << 1;
@@ -93,6 +195,32 @@ impl CPU {
         res
     }
 
+    // bit 0 goes into the carry, bit 7 becomes 0
+    pub(super) fn bit_shift_right_and_set_flags(&mut self, value: u8) -> u8 {
+        self.status.set(CPUFlags::CARRY, value & 1 == 1);
+        let res = value >> 1;
+        self.update_zero_and_negative_flags(res);
+        res
+    }
+
+    // like a left shift, but the old carry comes in at bit 0
+    pub(super) fn rotate_left_and_set_flags(&mut self, value: u8) -> u8 {
+        let carry_in = self.status.contains(CPUFlags::CARRY) as u8;
+        self.status.set(CPUFlags::CARRY, value >> 7 == 1);
+        let res = value << 1 | carry_in;
+        self.update_zero_and_negative_flags(res);
+        res
+    }
+
+    // like a right shift, but the old carry comes in at bit 7
+    pub(super) fn rotate_right_and_set_flags(&mut self, value: u8) -> u8 {
+        let carry_in = self.status.contains(CPUFlags::CARRY) as u8;
+        self.status.set(CPUFlags::CARRY, value & 1 == 1);
+        let res = value >> 1 | carry_in << 7;
+        self.update_zero_and_negative_flags(res);
+        res
+    }
+
     // shifts all the bits of the accumulator or memory contents one bit left
     // Bit 0 is set to 0 and bit 7 is placed in the carry flag
     pub(super) fn asl(&mut self, mode: &AddressingMode) {
@@ -100,24 +228,38 @@ impl CPU {
             // we have to deal with the accumulator
             self.register_a = self.bit_shift_left_and_set_flags(self.register_a);
         } else {
-            // Read from memory
-            let addr = self.get_operand_address(mode);
-            let val = self.mem_read(addr);
-            let res = self.bit_shift_left_and_set_flags(val);
-            // println!("writing at {addr}: val was {val}, res is {res}");
-            self.mem_write(addr, res);
+            self.read_modify_write(mode, Self::bit_shift_left_and_set_flags);
         };
     }
 
+    // Runs op on the byte at the operand, writes the result back and returns
+    // it. The 6502 writes the unmodified byte back first while op is being
+    // worked out; only a mapped device can tell, and some games rely on it
+    pub(super) fn read_modify_write<F>(&mut self, mode: &AddressingMode, op: F) -> u8
+    where
+        F: FnOnce(&mut Self, u8) -> u8,
+    {
+        let addr = self.get_write_address(mode);
+        let value = self.mem_read(addr);
+        if self.is_io(addr) {
+            self.mem_write(addr, value);
+        }
+        let res = op(self, value);
+        self.mem_write(addr, res);
+        res
+    }
+
     // if predicate if true then add the relative displacement to the program counter
     // to cause a branch to a new location
+    // the displacement is signed and relative to the next instruction
     fn add_next_val_to_pc_if(&mut self, predicate: bool) {
         if predicate {
-            let jmp = self.mem_read(self.program_counter);
-            self.program_counter = self
-                .program_counter
-                .wrapping_add(1)
-                .wrapping_add(jmp as u16);
+            let jmp = self.mem_read(self.program_counter) as i8;
+            let next = self.program_counter.wrapping_add(1);
+            self.program_counter = next.wrapping_add(jmp as u16);
+
+            self.branch_taken = true;
+            self.page_crossed = next & 0xFF00 != self.program_counter & 0xFF00;
         }
     }
 
@@ -175,8 +317,9 @@ impl CPU {
             self.status.remove(CPUFlags::ZERO);
         }
 
-        self.status.set(CPUFlags::OVERFLOW, value >> 6 == 1);
-        self.status.set(CPUFlags::NEGATIV, value >> 7 == 1);
+        self.status
+            .set(CPUFlags::OVERFLOW, value & 0b0100_0000 != 0);
+        self.status.set(CPUFlags::NEGATIV, value & 0b1000_0000 != 0);
     }
 
     // Forces the generation of an interrupt request
@@ -203,19 +346,99 @@ impl CPU {
         self.status.remove(CPUFlags::OVERFLOW);
     }
 
+    pub(super) fn sec(&mut self) {
+        self.status.insert(CPUFlags::CARRY);
+    }
+
+    // set the decimal mode flag
+    // on the NES this has no effect on arithmetic unless decimal mode is supported
+    pub(super) fn sed(&mut self) {
+        self.status.insert(CPUFlags::DECIMAL_MODE);
+    }
+
+    // Sets the program counter to the address specified by the operand
+    pub(super) fn jmp(&mut self) {
+        self.program_counter = self.mem_read_u16(self.program_counter);
+    }
+
+    // Pushes the address (minus one) of the return point on to the stack
+    // and then sets the program counter to the target memory address
+    pub(super) fn jsr(&mut self) {
+        // the PC is on the operand, so the last byte of this instruction is PC + 1
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.program_counter = self.mem_read_u16(self.program_counter);
+    }
+
     // Loads a byte of memory (value) into the accumulator
     // and sets the zero and negative flags as appropriate
     pub(super) fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
+        let addr = self.get_operand_address(mode);
         let value = self.mem_read(addr);
 
         self.register_a = value;
         self.update_zero_and_negative_flags(self.register_a);
     }
 
+    // Push a copy of the accumulator on to the stack
+    pub(super) fn pha(&mut self) {
+        self.stack_push(self.register_a);
+    }
+
+    // Push processor status
+    // the copy on the stack has B set, as it does for BRK
+    pub(super) fn php(&mut self) {
+        self.push_status(true);
+    }
+
+    // Pull accumulator
+    pub(super) fn pla(&mut self) {
+        self.register_a = self.stack_pop();
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    // Pull processor status
+    pub(super) fn plp(&mut self) {
+        self.pop_status();
+    }
+
+    // B only exists in the pushed copy, so it tells BRK/PHP apart from a
+    // hardware interrupt
+    pub(super) fn push_status(&mut self, b_flag: bool) {
+        let mut flags = self.status;
+        flags.set(CPUFlags::BREAK, b_flag);
+        if self.unused_bit_always_set {
+            flags.insert(CPUFlags::BREAK2);
+        }
+        self.stack_push(flags.bits());
+    }
+
+    // the break flags don't physically exist in the status register, so B is
+    // dropped and, unless configured otherwise, bit 5 reads as set. Every
+    // other bit is taken as is
+    fn pop_status(&mut self) {
+        self.status = CPUFlags::from_bits_truncate(self.stack_pop());
+        self.status.remove(CPUFlags::BREAK);
+        if self.unused_bit_always_set {
+            self.status.insert(CPUFlags::BREAK2);
+        }
+    }
+
+    // Return from interrupt
+    // pulls the processor flags from the stack followed by the program counter
+    pub(super) fn rti(&mut self) {
+        self.pop_status();
+        self.program_counter = self.stack_pop_u16();
+    }
+
+    // Return from subroutine
+    // pulls the program counter (minus one) from the stack
+    pub(super) fn rts(&mut self) {
+        self.program_counter = self.stack_pop_u16().wrapping_add(1);
+    }
+
     // Store address into register A
     pub(super) fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+        let addr = self.get_write_address(mode);
         self.mem_write(addr, self.register_a);
     }
 
@@ -0,0 +1,90 @@
+use crate::CPU::{CPUFlags, Mem, CPU};
+
+// Interrupts
+//
+// An interrupt finishes the current instruction, pushes the program counter
+// and the status register, disables further IRQs and jumps to the handler
+// whose address is stored in the interrupt's vector.
+//
+// This is an NMOS core: unlike the CMOS 65C02, the decimal flag is left
+// alone, the handler runs with whatever D was set to
+
+pub(super) struct Interrupt {
+    vector_addr: u16,
+    // the break flag is only set on the copy pushed by BRK/PHP
+    b_flag: bool,
+    cycles: usize,
+}
+
+// non-maskable, the PPU raises it at the start of vblank
+pub(super) const NMI: Interrupt = Interrupt {
+    vector_addr: 0xFFFA,
+    b_flag: false,
+    cycles: 7,
+};
+
+// maskable with the interrupt disable flag
+pub(super) const IRQ: Interrupt = Interrupt {
+    vector_addr: 0xFFFE,
+    b_flag: false,
+    cycles: 7,
+};
+
+impl CPU<'_> {
+    pub(super) fn interrupt(&mut self, interrupt: Interrupt) {
+        self.stack_push_u16(self.program_counter);
+
+        self.push_status(interrupt.b_flag);
+
+        // only the interrupt disable flag changes, see above
+        self.status.insert(CPUFlags::INTERRUPT_DISABLE);
+
+        self.cycles += interrupt.cycles;
+        self.program_counter = self.mem_read_u16(interrupt.vector_addr);
+    }
+
+    // Raise an interrupt line from outside, it's serviced by exec_one before
+    // the next instruction. A pending IRQ waits until I is cleared
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    pub fn trigger_irq(&mut self) {
+        self.irq_pending = true;
+    }
+
+    // Whether the next step enters a handler instead of running an opcode
+    pub(super) fn interrupt_due(&self) -> bool {
+        !self.delay_interrupt_poll
+            && (self.nmi_pending
+                || (self.irq_pending && !self.status.contains(CPUFlags::INTERRUPT_DISABLE)))
+    }
+
+    // NMI wins if both are pending, the IRQ is then taken after the handler
+    // clears I (or returns with RTI). Returns whether one was taken
+    pub(super) fn poll_interrupts(&mut self) -> bool {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(NMI);
+        } else if self.irq_pending && !self.status.contains(CPUFlags::INTERRUPT_DISABLE) {
+            self.irq_pending = false;
+            self.interrupt(IRQ);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    // Services a non-maskable interrupt before the next instruction
+    pub fn nmi(&mut self) {
+        self.interrupt(NMI);
+    }
+
+    // Services an interrupt request before the next instruction
+    // ignored while the interrupt disable flag is set
+    pub fn irq(&mut self) {
+        if !self.status.contains(CPUFlags::INTERRUPT_DISABLE) {
+            self.interrupt(IRQ);
+        }
+    }
+}
@@ -1,34 +1,257 @@
-use crate::CPU::CPU;
+use crate::CPU::{LoadError, CPU, STACK_BASE};
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::ops::{Deref, DerefMut};
+
+// size of the CPU address space, every memory backend covers all of it
+pub const ADDRESS_SPACE: usize = 0x10000;
+
+// memory mapped registers used by the snake demo
+// reading the random register yields a new byte every time
+pub const RANDOM_ADDR: u16 = 0x00FE;
+pub const INPUT_ADDR: u16 = 0x00FF;
+
+// [0x0000 .. 0x07FF] is the console's own RAM
+pub const INTERNAL_RAM_SIZE: usize = 0x0800;
+
+// [0x6000 .. 0x7FFF] is cartridge RAM, battery backed on cartridges with saves
+pub const SRAM_START: u16 = 0x6000;
+pub const SRAM_SIZE: usize = 0x2000;
+
+// last byte of the stack page
+const STACK_END: usize = 0x01FF;
+
+// [0x8000 .. 0xFFFF] is reserved for Program ROM
+pub const PRG_ROM_START: u16 = 0x8000;
+
+// called with (pc of the offending instruction, target address) when a
+// program writes into PRG ROM space while strict mode is on
+pub type RomWriteHandler = Box<dyn FnMut(u16, u16)>;
+
+// called with the pc when an opcode is fetched from outside PRG ROM
+pub type RamExecuteHandler = Box<dyn FnMut(u16)>;
+
+// One bit per address below PRG ROM, set once the address has been written
+// to. PRG ROM always counts as initialised
+pub(crate) struct WrittenMap {
+    bits: Vec<u64>,
+}
+
+impl WrittenMap {
+    pub(crate) fn new() -> Self {
+        WrittenMap {
+            bits: vec![0; PRG_ROM_START as usize / 64],
+        }
+    }
+
+    pub(crate) fn mark(&mut self, addr: u16) {
+        if addr < PRG_ROM_START {
+            self.bits[addr as usize / 64] |= 1 << (addr % 64);
+        }
+    }
+
+    pub(crate) fn is_written(&self, addr: u16) -> bool {
+        addr >= PRG_ROM_START || self.bits[addr as usize / 64] & (1 << (addr % 64)) != 0
+    }
+}
+
+// A user supplied peripheral mapped into the address space with CPU::map_io
+// it receives the full address that was accessed
+pub trait IoDevice {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, data: u8);
+}
+
+// Where the CPU keeps the bytes behind its address space
+pub(crate) enum Memory<'a> {
+    Owned(Box<[u8]>),
+    Borrowed(&'a mut [u8]),
+}
+
+impl Deref for Memory<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Memory::Owned(mem) => mem,
+            Memory::Borrowed(mem) => mem,
+        }
+    }
+}
+
+impl DerefMut for Memory<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Memory::Owned(mem) => mem,
+            Memory::Borrowed(mem) => mem,
+        }
+    }
+}
 
 pub trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+    // takes &mut self as some reads have side effects on the device behind them
+    fn mem_read(&mut self, addr: u16) -> u8;
 
     fn mem_write(&mut self, addr: u16, data: u8);
 
     // NES CPU uses Little-Endian addressing.
     // 8 least significant bits of an address will be stored before the 8 most significant bits
     // eg: LDA $8000     <=>    ad 00 80
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    // the high byte of a word at 0xFFFF comes from 0x0000
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
     }
 
+    // low byte first, this is what the vectors and absolute operands rely on
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+        self.mem_write(pos.wrapping_add(1), hi);
+    }
+
+    // Writes value to len consecutive addresses, wrapping past 0xFFFF
+    fn mem_fill(&mut self, start: u16, len: usize, value: u8) {
+        let mut addr = start;
+        for _ in 0..len {
+            self.mem_write(addr, value);
+            addr = addr.wrapping_add(1);
+        }
     }
 }
 
-impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
+impl CPU<'_> {
+    // Reads memory without any side effects, for debugging tools
+    // mapped devices and the random register are not consulted
+    pub fn mem_peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    // Copy of the 2 KiB of internal RAM, a much smaller save state than the
+    // whole address space. Taken directly, mapped devices aren't involved
+    pub fn ram_snapshot(&self) -> [u8; INTERNAL_RAM_SIZE] {
+        let mut ram = [0; INTERNAL_RAM_SIZE];
+        ram.copy_from_slice(&self.memory[..INTERNAL_RAM_SIZE]);
+        ram
+    }
+
+    pub fn ram_restore(&mut self, ram: &[u8; INTERNAL_RAM_SIZE]) {
+        self.memory[..INTERNAL_RAM_SIZE].copy_from_slice(ram);
+    }
+
+    // Zero the internal RAM as on a power cycle, ROM and the vectors stay
+    pub fn clear_ram(&mut self) {
+        self.memory[..INTERNAL_RAM_SIZE].fill(0);
+    }
+
+    // Whether save_sram is worth persisting: set by load_rom from the
+    // header's battery bit. 0x6000-0x7FFF is RAM either way, as plenty of
+    // boards carry work RAM there without a battery, it's just lost at
+    // power off on those
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    // Cartridge RAM contents, for the front-end to persist between sessions
+    // on cartridges with a battery, see has_battery
+    pub fn save_sram(&self) -> &[u8] {
+        let start = SRAM_START as usize;
+        &self.memory[start..start + SRAM_SIZE]
+    }
+
+    // Restores a save written out from save_sram. A shorter save only fills
+    // the start of the region
+    pub fn load_sram(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if data.len() > SRAM_SIZE {
+            return Err(LoadError::TooLarge {
+                len: data.len(),
+                capacity: SRAM_SIZE,
+            });
+        }
+
+        let start = SRAM_START as usize;
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    // The live part of the stack, from the top (the byte most recently
+    // pushed) down to 0x01FF, so return addresses read as little-endian
+    pub fn stack_slice(&self) -> &[u8] {
+        let top = STACK_BASE as usize + self.stack_pointer as usize + 1;
+        &self.memory[top.min(STACK_END + 1)..=STACK_END]
+    }
+
+    // A 16-bit read that refuses to touch I/O, for tools reading pointers
+    // out of memory. mem_read_u16 reads each byte once through mem_read as
+    // the CPU does, so a register under either byte fires its side effects
+    pub fn checked_read_u16(&mut self, pos: u16) -> Option<u16> {
+        let next = pos.wrapping_add(1);
+        if self.is_io(pos) || self.is_io(next) {
+            return None;
+        }
+        Some(self.mem_read_u16(pos))
+    }
+
+    // Whether reads of addr have side effects, i.e. it isn't plain memory
+    pub fn is_io(&self, addr: u16) -> bool {
+        (addr == RANDOM_ADDR && self.rng.is_some())
+            || self.io.iter().any(|(range, _)| range.contains(&addr))
+    }
+}
+
+impl Mem for CPU<'_> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        if let Some((_, device)) = self.io.iter_mut().find(|(range, _)| range.contains(&addr)) {
+            return device.read(addr);
+        }
+
+        if addr == RANDOM_ADDR {
+            if let Some(rng) = self.rng.as_mut() {
+                return rng.next_u8();
+            }
+        }
+
         self.memory[addr as usize]
     }
 
     // Write the data to the specified address
     fn mem_write(&mut self, addr: u16, data: u8) {
+        if self.read_only {
+            return;
+        }
+
+        if self.write_watch == Some((addr, data)) {
+            self.write_watch_hit = true;
+        }
+
+        if let Some(detector) = self.hang_detector.as_mut() {
+            detector.wrote = true;
+        }
+
+        if let Some((_, device)) = self.io.iter_mut().find(|(range, _)| range.contains(&addr)) {
+            device.write(addr, data);
+            return;
+        }
+
+        if addr >= PRG_ROM_START {
+            if let Some(handler) = self.rom_write_handler.as_mut() {
+                // every instruction that writes memory is still on its operand
+                handler(self.program_counter.wrapping_sub(1), addr);
+                return;
+            }
+        }
+
+        if let Some(history) = self.history.as_mut() {
+            history.record_write(addr, self.memory[addr as usize]);
+        }
+
+        if let Some((written, _)) = self.uninitialized_trap.as_mut() {
+            written.mark(addr);
+        }
+
         self.memory[addr as usize] = data;
     }
 }
@@ -1,7 +1,16 @@
-use lazy_static::lazy_static;
-
 use crate::CPU::AddressingMode;
-use std::collections::HashMap;
+
+use alloc::vec::Vec;
+
+// Only the official opcodes are documented by MOS. The illegal ones are side
+// effects of how the decoder works, some of them unstable across CPU
+// revisions, and the jams lock the CPU up until a reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpCodeCategory {
+    Official,
+    Illegal,
+    Jam,
+}
 
 pub struct OpCode {
     pub code: u8,
@@ -9,94 +18,244 @@ pub struct OpCode {
     pub len: u8,
     pub cycles: u8,
     pub mode: AddressingMode,
+    pub category: OpCodeCategory,
+    // takes a cycle more when indexing crosses a page, see cycles_for
+    pub page_cross_penalty: bool,
 }
 
 impl OpCode {
-    fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
+    const fn new(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
         OpCode {
             code,
             mnemonic,
             len,
             cycles,
             mode,
+            category: OpCodeCategory::Official,
+            page_cross_penalty: false,
+        }
+    }
+
+    // marks the indexed read modes, which only fix up the high byte of
+    // the address when they need to
+    const fn page_cross_penalty(mut self) -> Self {
+        self.page_cross_penalty = true;
+        self
+    }
+
+    const fn illegal(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
+        OpCode {
+            category: OpCodeCategory::Illegal,
+            ..OpCode::new(code, mnemonic, len, cycles, mode)
+        }
+    }
+
+    const fn jam(code: u8) -> Self {
+        OpCode {
+            category: OpCodeCategory::Jam,
+            ..OpCode::new(code, "JAM", 1, 0, AddressingMode::NoneAddressing)
+        }
+    }
+
+    // Cycles taken given what happened at runtime:
+    // - a taken branch costs one more, and another if it lands on a new page
+    // - indexed reads that cross a page boundary need one more to fix up the
+    //   high byte. Writes and read-modify-write always take that cycle, so
+    //   it's already in their base count
+    pub fn cycles_for(&self, page_crossed: bool, branch_taken: bool) -> u8 {
+        match self.mode {
+            AddressingMode::Relative => {
+                self.cycles + branch_taken as u8 + (branch_taken && page_crossed) as u8
+            }
+            _ if self.page_cross_penalty => self.cycles + page_crossed as u8,
+            _ => self.cycles,
         }
     }
+
+    pub fn is_official(&self) -> bool {
+        self.category == OpCodeCategory::Official
+    }
+
+    // Metadata (length, cycles, addressing mode) for an opcode byte
+    // without having to execute it
+    pub fn lookup(code: u8) -> Option<&'static OpCode> {
+        OPCODES_MAP[code as usize]
+    }
+}
+
+// Every encoding of an instruction, e.g. the eight addressing modes of LDA
+// the name is matched case-insensitively
+pub fn opcodes_for_mnemonic(name: &str) -> Vec<&'static OpCode> {
+    CPU_OPS_CODES
+        .iter()
+        .filter(|opcode| opcode.mnemonic.eq_ignore_ascii_case(name))
+        .collect()
+}
+
+// Just the name of the instruction behind code, None if it isn't in the table
+pub fn mnemonic(code: u8) -> Option<&'static str> {
+    OPCODES_MAP[code as usize].map(|opcode| opcode.mnemonic)
 }
 
-lazy_static! {
-    pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7d, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2d, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3d, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x39, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        // The first one addresses the accumulator
-        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::Absolute_X),
-
-        // branching only works for relative addressing mode which we can call none addressing
-        OpCode::new(0x90, "BCC", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xB0, "BCS", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xF0, "BEQ", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x30, "BMI", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xD0, "BNE", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x10, "BPL", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x50, "BVC", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x70, "BVS", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBD, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xB9, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xB1, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x9D, "STA", 3, 5, AddressingMode::Absolute_X),
-        OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
-        OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
-    ];
-
-
-  pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-    let mut map = HashMap::new();
-    for cpuop in &*CPU_OPS_CODES {
-      map.insert(cpuop.code, cpuop);
+#[rustfmt::skip]
+pub const CPU_OPS_CODES: &[OpCode] = &[
+    OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x7d, "ADC", 3, 4, AddressingMode::Absolute_X).page_cross_penalty(),
+    OpCode::new(0x79, "ADC", 3, 4, AddressingMode::Absolute_Y).page_cross_penalty(),
+    OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x71, "ADC", 2, 5, AddressingMode::Indirect_Y).page_cross_penalty(),
+
+    OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xFD, "SBC", 3, 4, AddressingMode::Absolute_X).page_cross_penalty(),
+    OpCode::new(0xF9, "SBC", 3, 4, AddressingMode::Absolute_Y).page_cross_penalty(),
+    OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xF1, "SBC", 2, 5, AddressingMode::Indirect_Y).page_cross_penalty(),
+
+    OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x2d, "AND", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x3d, "AND", 3, 4, AddressingMode::Absolute_X).page_cross_penalty(),
+    OpCode::new(0x39, "AND", 3, 4, AddressingMode::Absolute_Y).page_cross_penalty(),
+    OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x31, "AND", 2, 5, AddressingMode::Indirect_Y).page_cross_penalty(),
+
+    // The first one addresses the accumulator
+    OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::Absolute_X),
+
+    // branching only works for relative addressing mode
+    OpCode::new(0x90, "BCC", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+    OpCode::new(0xB0, "BCS", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+    OpCode::new(0xF0, "BEQ", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+    OpCode::new(0x30, "BMI", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+    OpCode::new(0xD0, "BNE", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+    OpCode::new(0x10, "BPL", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+    OpCode::new(0x50, "BVC", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+    OpCode::new(0x70, "BVS", 2, 2/*+1 if branch succeeds, +2 if to a new page*/, AddressingMode::Relative),
+
+    OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
+    OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute),
+    OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
+    OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xEA, "NOP", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBD, "LDA", 3, 4, AddressingMode::Absolute_X).page_cross_penalty(),
+    OpCode::new(0xB9, "LDA", 3, 4, AddressingMode::Absolute_Y).page_cross_penalty(),
+    OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xB1, "LDA", 2, 5, AddressingMode::Indirect_Y).page_cross_penalty(),
+
+    OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9D, "STA", 3, 5, AddressingMode::Absolute_X),
+    OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
+    OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
+
+    // Illegal opcodes
+
+    OpCode::illegal(0xA7, "LAX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::illegal(0xB7, "LAX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::illegal(0xAF, "LAX", 3, 4, AddressingMode::Absolute),
+    OpCode::illegal(0xBF, "LAX", 3, 4, AddressingMode::Absolute_Y).page_cross_penalty(),
+    OpCode::illegal(0xA3, "LAX", 2, 6, AddressingMode::Indirect_X),
+    OpCode::illegal(0xB3, "LAX", 2, 5, AddressingMode::Indirect_Y).page_cross_penalty(),
+
+    OpCode::illegal(0x07, "SLO", 2, 5, AddressingMode::ZeroPage),
+    OpCode::illegal(0x17, "SLO", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::illegal(0x0F, "SLO", 3, 6, AddressingMode::Absolute),
+    OpCode::illegal(0x1F, "SLO", 3, 7, AddressingMode::Absolute_X),
+    OpCode::illegal(0x1B, "SLO", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::illegal(0x03, "SLO", 2, 8, AddressingMode::Indirect_X),
+    OpCode::illegal(0x13, "SLO", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::illegal(0x27, "RLA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::illegal(0x37, "RLA", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::illegal(0x2F, "RLA", 3, 6, AddressingMode::Absolute),
+    OpCode::illegal(0x3F, "RLA", 3, 7, AddressingMode::Absolute_X),
+    OpCode::illegal(0x3B, "RLA", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::illegal(0x23, "RLA", 2, 8, AddressingMode::Indirect_X),
+    OpCode::illegal(0x33, "RLA", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::illegal(0x47, "SRE", 2, 5, AddressingMode::ZeroPage),
+    OpCode::illegal(0x57, "SRE", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::illegal(0x4F, "SRE", 3, 6, AddressingMode::Absolute),
+    OpCode::illegal(0x5F, "SRE", 3, 7, AddressingMode::Absolute_X),
+    OpCode::illegal(0x5B, "SRE", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::illegal(0x43, "SRE", 2, 8, AddressingMode::Indirect_X),
+    OpCode::illegal(0x53, "SRE", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::illegal(0x67, "RRA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::illegal(0x77, "RRA", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::illegal(0x6F, "RRA", 3, 6, AddressingMode::Absolute),
+    OpCode::illegal(0x7F, "RRA", 3, 7, AddressingMode::Absolute_X),
+    OpCode::illegal(0x7B, "RRA", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::illegal(0x63, "RRA", 2, 8, AddressingMode::Indirect_X),
+    OpCode::illegal(0x73, "RRA", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::jam(0x02), OpCode::jam(0x12), OpCode::jam(0x22), OpCode::jam(0x32),
+    OpCode::jam(0x42), OpCode::jam(0x52), OpCode::jam(0x62), OpCode::jam(0x72),
+    OpCode::jam(0x92), OpCode::jam(0xB2), OpCode::jam(0xD2), OpCode::jam(0xF2),
+];
+
+// Indexed by the opcode byte, so dispatch is a plain array lookup
+// built at compile time which keeps the core free of std collections
+pub static OPCODES_MAP: [Option<&OpCode>; 256] = build_opcodes_map(CPU_OPS_CODES);
+
+const fn build_opcodes_map(ops: &'static [OpCode]) -> [Option<&'static OpCode>; 256] {
+    let mut map = [None; 256];
+    let mut i = 0;
+    while i < ops.len() {
+        map[ops[i].code as usize] = Some(&ops[i]);
+        i += 1;
     }
     map
-  };
 }
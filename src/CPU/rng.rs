@@ -0,0 +1,35 @@
+// Source of randomness for anything the emulator can't compute on its own
+// (e.g. the snake demo polls a random byte every frame)
+//
+// Kept behind a trait so tests can plug in something deterministic
+pub trait EmuRng {
+    fn next_u8(&mut self) -> u8;
+}
+
+// Linear congruential generator
+// Same constants as Numerical Recipes, good enough for games and
+// makes every run reproducible from its seed
+pub struct Lcg {
+    state: u32,
+}
+
+impl Lcg {
+    pub fn new(seed: u32) -> Self {
+        Lcg { state: seed }
+    }
+}
+
+// the default seed, so even an unconfigured emulator is reproducible
+impl Default for Lcg {
+    fn default() -> Self {
+        Lcg::new(0x6502)
+    }
+}
+
+impl EmuRng for Lcg {
+    fn next_u8(&mut self) -> u8 {
+        self.state = self.state.wrapping_mul(1664525).wrapping_add(1013904223);
+        // the high bits of an LCG are far more random than the low ones
+        (self.state >> 24) as u8
+    }
+}
@@ -0,0 +1,75 @@
+use crate::CPU::{CPUFlags, OpCode, RunOutcome, CPU};
+
+use alloc::{format, string::String, vec::Vec};
+
+// Diagnostics describing what instructions did, for debugging and for
+// learning how the 6502 flags behave
+
+// The flags that differ between before and after, e.g.
+// "CARRY set, ZERO cleared", or None if nothing changed
+pub fn describe_flag_changes(before: CPUFlags, after: CPUFlags) -> Option<String> {
+    let changes: Vec<String> = (before ^ after)
+        .iter_names()
+        .map(|(name, flag)| {
+            let change = if after.contains(flag) {
+                "set"
+            } else {
+                "cleared"
+            };
+            format!("{} {}", name, change)
+        })
+        .collect();
+
+    if changes.is_empty() {
+        None
+    } else {
+        Some(changes.join(", "))
+    }
+}
+
+impl CPU<'_> {
+    // One line describing the instruction about to execute and the
+    // registers before it, in the spirit of the nestest log:
+    // 8000  A9 05     LDA #$05      A:00 X:00 Y:00 P:24 SP:FD
+    pub fn trace(&self) -> String {
+        let pc = self.program_counter;
+        let instruction = self
+            .disassemble(pc)
+            .unwrap_or_else(|| format!(".byte ${:02X}", self.mem_peek(pc)));
+
+        format!(
+            "{:04X}  {:<8}  {:<12}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            pc,
+            self.instruction_bytes(pc),
+            instruction,
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.status.as_nestest_byte(),
+            self.stack_pointer
+        )
+    }
+
+    // Runs like run_with_callback, but hands log a line such as
+    // "ADC at $8002: CARRY set, OVERFLOW set" for every instruction that
+    // changed any flags
+    pub fn run_with_flag_log<F>(&mut self, mut log: F) -> RunOutcome
+    where
+        F: FnMut(String),
+    {
+        // the instruction that's about to run and the flags before it
+        let mut last: Option<(u16, &'static str, CPUFlags)> = None;
+
+        self.run_with_callback(|cpu| {
+            if let Some((pc, mnemonic, before)) = last {
+                if let Some(changes) = describe_flag_changes(before, cpu.status) {
+                    log(format!("{} at ${:04X}: {}", mnemonic, pc, changes));
+                }
+            }
+
+            let mnemonic = OpCode::lookup(cpu.mem_peek(cpu.program_counter))
+                .map_or("???", |opcode| opcode.mnemonic);
+            last = Some((cpu.program_counter, mnemonic, cpu.status));
+        })
+    }
+}
@@ -0,0 +1,19 @@
+// The CPU core only needs an allocator, so it can run on targets without the
+// standard library by disabling the default `std` feature
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+extern crate alloc;
+
+#[allow(non_snake_case)]
+pub mod CPU;
+pub mod mapper;
+pub mod rom;
+
+#[cfg(feature = "std")]
+pub mod timing;
+
+#[cfg(feature = "terminal")]
+pub mod terminal;
+
+#[cfg(test)]
+mod tests;
@@ -1,7 +1,3 @@
-#[allow(non_snake_case)]
-mod CPU;
-mod tests;
-
 fn main() {
     // bugzmanov.github.io/nes_ebook/
     println!("Hello, world!");
@@ -0,0 +1,105 @@
+use crate::rom::{Rom, CHR_BANK_SIZE, PRG_BANK_SIZE};
+use crate::CPU::IoDevice;
+
+use alloc::{vec, vec::Vec};
+
+// Cartridge mappers
+//
+// A mapper sits on the CPU bus over 0x8000-0xFFFF, so it's an IoDevice the
+// CPU reaches through map_io: reads come from whichever PRG bank is
+// selected and writes go to its bank registers instead of the ROM. It also
+// decides which CHR bank the PPU sees, which chr_read exposes until the PPU
+// can be wired up to it
+
+pub trait Mapper: IoDevice {
+    // a byte of the 8 KiB pattern table window at PPU address 0x0000-0x1FFF
+    fn chr_read(&self, addr: u16) -> u8;
+}
+
+// CHR ROM, or 8 KiB of CHR RAM for boards with none
+fn chr_or_ram(rom: &Rom) -> Vec<u8> {
+    if rom.chr_rom.is_empty() {
+        vec![0; CHR_BANK_SIZE]
+    } else {
+        rom.chr_rom.clone()
+    }
+}
+
+// UxROM (mapper 2): writes anywhere in ROM select the 16 KiB bank seen at
+// 0x8000-0xBFFF, 0xC000-0xFFFF always shows the last bank
+pub struct Uxrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    bank: usize,
+}
+
+impl Uxrom {
+    pub fn new(rom: &Rom) -> Self {
+        Uxrom {
+            prg: rom.prg_rom.clone(),
+            chr: chr_or_ram(rom),
+            bank: 0,
+        }
+    }
+
+    fn banks(&self) -> usize {
+        self.prg.len() / PRG_BANK_SIZE
+    }
+}
+
+impl IoDevice for Uxrom {
+    fn read(&mut self, addr: u16) -> u8 {
+        let offset = addr as usize & (PRG_BANK_SIZE - 1);
+        let bank = if addr < 0xC000 {
+            self.bank
+        } else {
+            self.banks() - 1
+        };
+        self.prg[bank * PRG_BANK_SIZE + offset]
+    }
+
+    fn write(&mut self, _addr: u16, data: u8) {
+        self.bank = data as usize % self.banks();
+    }
+}
+
+impl Mapper for Uxrom {
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % CHR_BANK_SIZE]
+    }
+}
+
+// CNROM (mapper 3): PRG ROM is fixed as on NROM, a 16 KiB board mirrored
+// at 0xC000. Writes anywhere in ROM pick the 8 KiB CHR bank with bits 0-1
+pub struct Cnrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_bank: usize,
+}
+
+impl Cnrom {
+    pub fn new(rom: &Rom) -> Self {
+        Cnrom {
+            prg: rom.prg_rom.clone(),
+            chr: chr_or_ram(rom),
+            chr_bank: 0,
+        }
+    }
+}
+
+impl IoDevice for Cnrom {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.prg[addr as usize % self.prg.len()]
+    }
+
+    fn write(&mut self, _addr: u16, data: u8) {
+        let banks = self.chr.len() / CHR_BANK_SIZE;
+        self.chr_bank = (data & 0b11) as usize % banks;
+    }
+}
+
+impl Mapper for Cnrom {
+    fn chr_read(&self, addr: u16) -> u8 {
+        self.chr[self.chr_bank * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE]
+    }
+}
@@ -0,0 +1,187 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+// iNES cartridge images
+//
+// A 16 byte header, an optional 512 byte trainer, then the PRG ROM banks
+// followed by the CHR ROM banks. NES 2.0 is a superset of the header that
+// older parsers read as plain iNES
+//
+// bytes 0-3  "NES" followed by 0x1A
+// byte 4     PRG ROM size in 16 KiB units
+// byte 5     CHR ROM size in 8 KiB units
+// byte 6     mirroring, battery, trainer, mapper bits 0-3
+// byte 7     console type, NES 2.0 marker (bits 2-3 == 2), mapper bits 4-7
+// byte 8     NES 2.0: mapper bits 8-11, submapper
+// byte 9     NES 2.0: PRG and CHR ROM size high nibbles
+// byte 10-11 NES 2.0: PRG and CHR RAM sizes
+
+const MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+pub(crate) const PRG_BANK_SIZE: usize = 0x4000;
+pub(crate) const CHR_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    INes,
+    Nes2,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RomError {
+    // not even a whole header
+    TooShort { len: usize },
+    // the file doesn't start with "NES\x1A"
+    BadMagic,
+    // the header declares more trainer/PRG/CHR data than the file holds
+    Truncated { expected: usize, len: usize },
+    // NES 2.0 exponent-multiplier ROM sizes, used only by a handful of
+    // oversized homebrew images
+    ExponentSize,
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::TooShort { len } => write!(
+                f,
+                "file is {} bytes, shorter than the {} byte header",
+                len, HEADER_SIZE
+            ),
+            RomError::BadMagic => write!(f, "not an iNES file, the magic number is wrong"),
+            RomError::Truncated { expected, len } => write!(
+                f,
+                "header declares {} bytes of data but the file is only {} bytes",
+                expected, len
+            ),
+            RomError::ExponentSize => {
+                write!(f, "NES 2.0 exponent-multiplier ROM sizes are not supported")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RomError {}
+
+// What a front-end shows about a cartridge, without the ROM data itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomInfo {
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub battery: bool,
+}
+
+pub struct Rom {
+    pub format: RomFormat,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u16,
+    // always 0 for iNES 1.0
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    // cartridge RAM in bytes, only NES 2.0 headers say how much there is
+    pub prg_ram_size: usize,
+    pub chr_ram_size: usize,
+    pub battery: bool,
+}
+
+impl Rom {
+    pub fn info(&self) -> RomInfo {
+        RomInfo {
+            mapper: self.mapper,
+            submapper: self.submapper,
+            mirroring: self.mirroring,
+            prg_rom_size: self.prg_rom.len(),
+            chr_rom_size: self.chr_rom.len(),
+            battery: self.battery,
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Rom, RomError> {
+        if data.len() < HEADER_SIZE {
+            return Err(RomError::TooShort { len: data.len() });
+        }
+        if data[0..4] != MAGIC {
+            return Err(RomError::BadMagic);
+        }
+
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let format = if flags7 & 0x0C == 0x08 {
+            RomFormat::Nes2
+        } else {
+            RomFormat::INes
+        };
+
+        let mut mapper = (flags7 & 0xF0) as u16 | (flags6 >> 4) as u16;
+        let mut submapper = 0;
+        let mut prg_banks = data[4] as usize;
+        let mut chr_banks = data[5] as usize;
+        let mut prg_ram_size = 0;
+        let mut chr_ram_size = 0;
+
+        if format == RomFormat::Nes2 {
+            if data[9] & 0x0F == 0x0F || data[9] >> 4 == 0x0F {
+                return Err(RomError::ExponentSize);
+            }
+            mapper |= ((data[8] & 0x0F) as u16) << 8;
+            submapper = data[8] >> 4;
+            prg_banks |= ((data[9] & 0x0F) as usize) << 8;
+            chr_banks |= ((data[9] >> 4) as usize) << 8;
+            prg_ram_size = ram_size(data[10]);
+            chr_ram_size = ram_size(data[11]);
+        }
+
+        let mirroring = if flags6 & 0b1000 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0b1 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let has_trainer = flags6 & 0b100 != 0;
+        let prg_start = HEADER_SIZE + if has_trainer { TRAINER_SIZE } else { 0 };
+        let chr_start = prg_start + prg_banks * PRG_BANK_SIZE;
+        let chr_end = chr_start + chr_banks * CHR_BANK_SIZE;
+        if chr_end > data.len() {
+            return Err(RomError::Truncated {
+                expected: chr_end,
+                len: data.len(),
+            });
+        }
+
+        Ok(Rom {
+            format,
+            prg_rom: data[prg_start..chr_start].to_vec(),
+            chr_rom: data[chr_start..chr_end].to_vec(),
+            mapper,
+            submapper,
+            mirroring,
+            prg_ram_size,
+            chr_ram_size,
+            battery: flags6 & 0b10 != 0,
+        })
+    }
+}
+
+// volatile RAM size from the low nibble of byte 10/11: 64 << n bytes, or
+// none at all for 0
+fn ram_size(byte: u8) -> usize {
+    match byte & 0x0F {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
@@ -0,0 +1,43 @@
+use crate::CPU::CPU;
+
+use std::io::{self, Write};
+
+// Terminal display for the snake demo's 32x32 screen
+//
+// The screen is memory mapped at [0x0200 .. 0x05FF], one byte per pixel,
+// row by row. Each pixel is drawn as two spaces with an ANSI background
+// color so it comes out roughly square
+
+pub const SCREEN_START: u16 = 0x0200;
+pub const SCREEN_SIZE: usize = 32;
+
+// The snake demo's palette, as ANSI 256 color codes
+fn ansi_color(byte: u8) -> u8 {
+    match byte {
+        0 => 16,       // black
+        1 => 231,      // white
+        2 | 9 => 244,  // grey
+        3 | 10 => 196, // red
+        4 | 11 => 46,  // green
+        5 | 12 => 21,  // blue
+        6 | 13 => 201, // magenta
+        7 | 14 => 226, // yellow
+        _ => 51,       // cyan
+    }
+}
+
+// Draws the current screen over the previous frame
+pub fn render<W: Write>(cpu: &CPU, out: &mut W) -> io::Result<()> {
+    // cursor to the top left so each frame overwrites the last one
+    write!(out, "\x1b[H")?;
+
+    for row in 0..SCREEN_SIZE {
+        for col in 0..SCREEN_SIZE {
+            let addr = SCREEN_START + (row * SCREEN_SIZE + col) as u16;
+            write!(out, "\x1b[48;5;{}m  ", ansi_color(cpu.mem_peek(addr)))?;
+        }
+        writeln!(out, "\x1b[0m")?;
+    }
+
+    out.flush()
+}
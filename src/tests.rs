@@ -1,9 +1,101 @@
-#[cfg(test)]
+use crate::mapper::{Cnrom, Mapper, Uxrom};
+use crate::rom::{Mirroring, Rom, RomError, RomFormat};
+#[cfg(feature = "std")]
+use crate::timing::{Clock, FrameLimiter};
+use crate::CPU::{
+    decode, describe_flag_changes, mnemonic, opcodes_for_mnemonic, rng::Lcg, AddressingMode,
+    CPUFlags, CpuBuilder, CpuState, ExecError, Instruction, IoDevice, LoadError, Mem, OpCode,
+    OpCodeCategory, Pattern, RunOutcome, RunState, CPU, STACK_BASE,
+};
 
-use crate::CPU::{CPU, CPUFlags};
+#[cfg(feature = "std")]
+use std::time::Duration;
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc, sync::Mutex};
+
+mod reference;
 
 // Pro tip: Use the mac os calculator in programmer mode by going to View > Programmer
 
+// Runs cpu to completion collecting a trace line per instruction and
+// panics at the first one that differs from expected, or if it ran for
+// more or fewer instructions than expected
+pub(crate) fn assert_trace_matches(cpu: &mut CPU, expected: &[&str]) {
+    let mut actual = Vec::new();
+    cpu.run_with_callback(|cpu| actual.push(cpu.trace()));
+
+    for (line, (expected, actual)) in expected.iter().zip(&actual).enumerate() {
+        assert!(
+            expected == actual,
+            "trace diverges at line {}\nexpected: {}\n  actual: {}",
+            line + 1,
+            expected,
+            actual
+        );
+    }
+    assert_eq!(
+        expected.len(),
+        actual.len(),
+        "trace has {} lines, expected {}",
+        actual.len(),
+        expected.len()
+    );
+}
+
+// Checks every (address, value) pair and reports all the mismatches in one
+// panic. Reads with mem_peek so asserting can't disturb mapped devices
+pub(crate) fn assert_memory_eq(cpu: &CPU, expected: &[(u16, u8)]) {
+    let diffs: Vec<String> = expected
+        .iter()
+        .filter(|&&(addr, value)| cpu.mem_peek(addr) != value)
+        .map(|&(addr, value)| {
+            format!(
+                "  ${:04X}: expected ${:02X}, found ${:02X}",
+                addr,
+                value,
+                cpu.mem_peek(addr)
+            )
+        })
+        .collect();
+
+    assert!(
+        diffs.is_empty(),
+        "memory differs at {} of {} addresses\n{}",
+        diffs.len(),
+        expected.len(),
+        diffs.join("\n")
+    );
+}
+
+// Opcode coverage
+// CPU::run records every opcode it dispatches while testing, so that we can
+// see which ones no test exercises. The report needs the whole suite to have
+// run first, so run it single threaded (tests run in name order) with:
+//
+//  cargo test -- --include-ignored --test-threads=1 --nocapture
+static EXECUTED_OPCODES: Mutex<BTreeSet<u8>> = Mutex::new(BTreeSet::new());
+
+pub(crate) fn record_opcode(code: u8) {
+    EXECUTED_OPCODES.lock().unwrap().insert(code);
+}
+
+#[test]
+#[ignore]
+fn zz_opcode_coverage_report() {
+    let executed = EXECUTED_OPCODES.lock().unwrap();
+    let untested: Vec<_> = (0..=0xFFu8)
+        .filter_map(OpCode::lookup)
+        .filter(|opcode| !executed.contains(&opcode.code))
+        .collect();
+
+    println!("{} opcodes never executed by a test:", untested.len());
+    for opcode in untested {
+        println!(
+            "  {:02X} {} {:?}",
+            opcode.code, opcode.mnemonic, opcode.mode
+        );
+    }
+}
+
 #[test]
 fn test_add_with_carry_overflow() {
     let mut cpu = CPU::new();
@@ -20,6 +112,22 @@ fn test_add_with_carry_overflow() {
     assert!(!cpu.status.contains(CPUFlags::CARRY));
 }
 
+#[test]
+fn test_adc_with_carry_in() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![
+        0x38, // sec
+        0xA9, // lda
+        0x10, //
+        0x69, // adc
+        0x10, //
+        0x00, // brk
+    ]);
+
+    assert_eq!(cpu.register_a, 0x21);
+    assert!(!cpu.carry());
+}
+
 #[test]
 fn test_asl_adc_carry() {
     let mut cpu = CPU::new();
@@ -65,7 +173,7 @@ fn test_and() {
         0xFF, // all 1s
         0x29, // AND
         0xA0, // 1010 0000
-        0x00
+        0x00,
     ]);
 
     assert_eq!(cpu.register_a, 0xA0);
@@ -88,6 +196,15 @@ fn test_0xa9_lda_zero_flag() {
     assert!(cpu.status.bits() & 0b0000_0010 == 0b10);
 }
 
+#[test]
+fn test_0xa9_lda_zero_flag_getters() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![0xA9, 0x00, 0x00]);
+    assert_eq!(cpu.zero(), cpu.status.bits() & 0b0000_0010 == 0b10);
+    assert!(cpu.zero());
+    assert!(!cpu.negative());
+}
+
 #[test]
 fn test_0xaa_tax_move_a_to_x() {
     let mut cpu = CPU::new();
@@ -119,3 +236,1961 @@ fn test_inx_overflow() {
 //     cpu.load_and_run(vec![0x00]);
 //     assert_ne!(cpu.status & 0b0010_0000, 0);
 // }
+
+#[test]
+fn test_seeded_rng_register() {
+    let mut cpu = CpuBuilder::new().rng(Box::new(Lcg::new(42))).build();
+    cpu.load_and_run(vec![
+        0xA5, // lda zero page
+        0xFE, // random register
+        0xAA, // tax
+        0xA5, // lda zero page
+        0xFE, // random register
+        0x00, // brk
+    ]);
+
+    assert_eq!(cpu.register_x, 0x40);
+    assert_eq!(cpu.register_a, 0x16);
+}
+
+#[test]
+fn test_input_register() {
+    let mut cpu = CPU::new();
+    cpu.set_input(0x77);
+    cpu.load_and_run(vec![0xA5, 0xFF, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x77);
+}
+
+#[test]
+fn test_opcode_lookup() {
+    let opcode = OpCode::lookup(0xA9).unwrap();
+
+    assert_eq!(opcode.mnemonic, "LDA");
+    assert_eq!(opcode.len, 2);
+    assert_eq!(opcode.mode, AddressingMode::Immediate);
+    assert!(OpCode::lookup(0xFF).is_none());
+}
+
+#[test]
+fn test_absolute_x_page_cross_dummy_read() {
+    let mut cpu = CpuBuilder::new().rng(Box::new(Lcg::new(42))).build();
+    cpu.load_and_run(vec![
+        0xA9, // lda
+        0xFF, //
+        0xAA, // tax
+        0xBD, // lda absolute x
+        0xFF, // 0x00FF + 0xFF = 0x01FE crosses a page,
+        0x00, // so the dummy read lands on 0x00FE
+        0xA5, // lda zero page
+        0xFE, // random register
+        0x00, // brk
+    ]);
+
+    // the dummy read consumed the first random byte, so lda $FE sees the second
+    assert_eq!(cpu.register_a, 0x16);
+}
+
+#[test]
+fn test_absolute_x_store_always_dummy_reads() {
+    let mut cpu = CpuBuilder::new().rng(Box::new(Lcg::new(42))).build();
+    cpu.load_and_run(vec![
+        0x9D, // sta absolute x
+        0xFE, // 0x00FE + 0 stays on the page,
+        0x00, // but the store still reads 0x00FE first
+        0xA5, // lda zero page
+        0xFE, // random register
+        0x00, // brk
+    ]);
+
+    assert_eq!(cpu.register_a, 0x16);
+}
+
+struct ConstantDevice(u8);
+
+impl IoDevice for ConstantDevice {
+    fn read(&mut self, _addr: u16) -> u8 {
+        self.0
+    }
+
+    fn write(&mut self, _addr: u16, _data: u8) {}
+}
+
+// Holds one byte and logs every write it sees
+struct RecordingDevice {
+    value: u8,
+    writes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl IoDevice for RecordingDevice {
+    fn read(&mut self, _addr: u16) -> u8 {
+        self.value
+    }
+
+    fn write(&mut self, _addr: u16, data: u8) {
+        self.value = data;
+        self.writes.borrow_mut().push(data);
+    }
+}
+
+#[test]
+fn test_map_io_read() {
+    let mut cpu = CPU::new();
+    cpu.map_io(0x6000..=0x6000, Box::new(ConstantDevice(0x42)));
+    cpu.load_and_run(vec![
+        0xAD, // lda absolute
+        0x00, 0x60, // $6000
+        0x00, // brk
+    ]);
+
+    assert_eq!(cpu.register_a, 0x42);
+}
+
+#[test]
+fn test_jsr_rts_cycles() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![
+        0x20, // jsr
+        0x04, 0x80, // $8004
+        0x00, // brk
+        0x60, // rts
+    ]);
+
+    assert_eq!(cpu.cycles, 12);
+    assert_eq!(cpu.program_counter, 0x8004);
+    assert_eq!(cpu.stack_pointer, 0xFD);
+}
+
+#[test]
+fn test_rts_wraps_to_zero_page() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA9, 0xFF, // lda #$ff
+        0x48, 0x48, // push $ffff
+        0x60, // rts
+    ])
+    .unwrap();
+    cpu.reset();
+
+    for _ in 0..4 {
+        cpu.exec_one();
+    }
+    assert_eq!(cpu.program_counter, 0x0000);
+}
+
+#[test]
+fn test_pc_wraps_past_end_of_memory() {
+    let mut cpu = CPU::new();
+    cpu.load_segments(&[(0xFFFF, &[0xE8])]).unwrap(); // inx
+    cpu.program_counter = 0xFFFF;
+    cpu.exec_one();
+    assert_eq!(cpu.program_counter, 0x0000);
+
+    cpu.load_segments(&[(0xFFFE, &[0xA9, 0x05])]).unwrap(); // lda #$05
+    cpu.program_counter = 0xFFFE;
+    cpu.exec_one();
+    assert_eq!(cpu.register_a, 0x05);
+    assert_eq!(cpu.program_counter, 0x0000);
+
+    // an operand that wraps round into the zero page
+    cpu.load_segments(&[(0xFFFF, &[0xA9]), (0x0000, &[0x07])])
+        .unwrap();
+    cpu.program_counter = 0xFFFF;
+    cpu.exec_one();
+    assert_eq!(cpu.register_a, 0x07);
+    assert_eq!(cpu.program_counter, 0x0001);
+}
+
+#[test]
+fn test_jsr_return_address_wraps() {
+    let mut cpu = CPU::new();
+    // jsr $8000 with its last operand byte at 0x0000
+    cpu.load_segments(&[(0xFFFE, &[0x20, 0x00]), (0x0000, &[0x80])])
+        .unwrap();
+    cpu.mem_write_u16(STACK_BASE | 0xFC, 0xFFFF);
+    cpu.program_counter = 0xFFFE;
+    cpu.stack_pointer = 0xFD;
+
+    cpu.exec_one();
+    assert_eq!(cpu.program_counter, 0x8000);
+    assert_eq!(cpu.stack_pointer, 0xFB);
+    assert_eq!(cpu.mem_read_u16(STACK_BASE | 0xFC), 0x0000);
+}
+
+#[test]
+fn test_indirect_pointer_wraps_in_zero_page() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xB1, // lda indirect y
+        0xFF, // pointer lo at 0xFF, hi at 0x00
+        0x00, // brk
+    ])
+    .unwrap();
+    cpu.reset();
+    cpu.mem_write(0x00FF, 0x34);
+    cpu.mem_write(0x0000, 0x12);
+    cpu.mem_write(0x0100, 0x56); // would be used if the pointer escaped zero page
+    cpu.mem_write(0x1234, 0x99);
+    cpu.mem_write(0x5634, 0x11);
+    cpu.run();
+
+    assert_eq!(cpu.read_zp_pointer(0xFF), 0x1234);
+    assert_eq!(cpu.register_a, 0x99);
+}
+
+#[test]
+fn test_strict_rom_reports_write() {
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&writes);
+    let mut cpu = CpuBuilder::new()
+        .strict_rom(Box::new(move |pc, addr| log.borrow_mut().push((pc, addr))))
+        .build();
+    cpu.load_and_run(vec![
+        0xA9, // lda
+        0x55, //
+        0x8D, // sta absolute
+        0x00, 0x80, // $8000
+        0x00, // brk
+    ]);
+
+    assert_eq!(*writes.borrow(), vec![(0x8002, 0x8000)]);
+    // the write was dropped, so the program is intact
+    assert_eq!(cpu.mem_read(0x8000), 0xA9);
+}
+
+#[test]
+fn test_disassemble_branch_target() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xE8, // inx
+        0xD0, // bne
+        0xFD, // -3, back to the inx
+        0xD0, // bne
+        0x10, // +16
+    ])
+    .unwrap();
+
+    assert_eq!(cpu.disassemble(0x8001).unwrap(), "BNE $8000");
+    assert_eq!(cpu.disassemble(0x8003).unwrap(), "BNE $8015");
+}
+
+#[test]
+fn test_load_rejects_program_larger_than_prg_rom() {
+    let mut cpu = CPU::new();
+    let result = cpu.load(vec![0xEA; 33 * 1024]);
+
+    assert_eq!(
+        result,
+        Err(LoadError::TooLarge {
+            len: 33 * 1024,
+            capacity: 0x7FFA
+        })
+    );
+}
+
+#[test]
+fn test_load_rejects_program_overlapping_vectors() {
+    let mut cpu = CPU::new();
+    cpu.load_segments(&[(0xFFF8, &[0x11; 8])]).unwrap();
+
+    assert_eq!(
+        cpu.load(vec![0xEA; 0x7FFB]),
+        Err(LoadError::TooLarge {
+            len: 0x7FFB,
+            capacity: 0x7FFA
+        })
+    );
+    // nothing was written, the vectors are as they were
+    assert_eq!(cpu.mem_peek(0x8000), 0x00);
+    assert_eq!(cpu.reset_vector(), 0x1111);
+
+    // right up to the vectors is fine
+    assert_eq!(cpu.load(vec![0xEA; 0x7FFA]), Ok(()));
+    assert_eq!(cpu.reset_vector(), 0x8000);
+}
+
+const DECIMAL_ADC: [u8; 6] = [
+    0xF8, // sed
+    0xA9, // lda
+    0x15, // 15 in bcd
+    0x69, // adc
+    0x27, // 27 in bcd
+    0x00, // brk
+];
+
+#[test]
+fn test_adc_ignores_decimal_flag_on_nes() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(DECIMAL_ADC.to_vec());
+
+    assert!(cpu.decimal());
+    assert_eq!(cpu.register_a, 0x3C);
+}
+
+#[test]
+fn test_adc_decimal_mode_on_generic_6502() {
+    let mut cpu = CpuBuilder::new().decimal_mode_supported(true).build();
+    cpu.load_and_run(DECIMAL_ADC.to_vec());
+
+    assert_eq!(cpu.register_a, 0x42);
+    assert!(!cpu.carry());
+}
+
+#[test]
+fn test_current_instruction_in_callback() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x05, 0xAA, 0xE8, 0x00]).unwrap();
+    cpu.reset();
+
+    let mut seen = Vec::new();
+    cpu.run_with_callback(|cpu| seen.push(cpu.current_instruction().unwrap()));
+
+    assert_eq!(
+        seen,
+        vec![
+            (0x8000, "LDA #$05".to_string()),
+            (0x8002, "TAX".to_string()),
+            (0x8003, "INX".to_string()),
+            (0x8004, "BRK".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_run_until_pauses_and_resumes() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xE8; 8]).unwrap();
+    cpu.reset();
+
+    cpu.run_until(|cpu| cpu.register_x == 5);
+    assert_eq!(cpu.register_x, 5);
+    assert_eq!(cpu.program_counter, 0x8005);
+    assert_eq!(cpu.state, RunState::Paused);
+
+    cpu.run_until(|cpu| cpu.register_x == 7);
+    assert_eq!(cpu.register_x, 7);
+    assert_eq!(cpu.program_counter, 0x8007);
+}
+
+#[test]
+fn test_instructions_iterator() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x05, 0x8D, 0x00, 0x02, 0xE8, 0x00])
+        .unwrap();
+
+    let listing: Vec<_> = cpu.instructions(0x8000).take(3).collect();
+
+    assert_eq!(
+        listing,
+        vec![
+            (0x8000, "LDA #$05".to_string()),
+            (0x8002, "STA $0200".to_string()),
+            (0x8005, "INX".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_mem_fill() {
+    let mut cpu = CPU::new();
+    cpu.mem_fill(0x0200, 0x100, 0x01);
+
+    assert_eq!(cpu.mem_read(0x01FF), 0x00);
+    assert_eq!(cpu.mem_read(0x0200), 0x01);
+    assert_eq!(cpu.mem_read(0x0280), 0x01);
+    assert_eq!(cpu.mem_read(0x02FF), 0x01);
+    assert_eq!(cpu.mem_read(0x0300), 0x00);
+
+    cpu.mem_fill(0xFFFF, 2, 0x02);
+    assert_eq!(cpu.mem_read(0xFFFF), 0x02);
+    assert_eq!(cpu.mem_read(0x0000), 0x02);
+}
+
+#[test]
+fn test_mem_read_u16_reset_vector() {
+    let mut cpu = CPU::new();
+    cpu.map_io(0x2002..=0x2002, Box::new(ConstantDevice(0x80)));
+    cpu.load(vec![0x00]).unwrap();
+
+    assert_eq!(cpu.mem_read_u16(0xFFFC), 0x8000);
+}
+
+#[test]
+fn test_checked_read_u16_rejects_io_register() {
+    let mut cpu = CPU::new();
+    cpu.map_io(0x2002..=0x2002, Box::new(ConstantDevice(0x80)));
+    cpu.load(vec![0x00]).unwrap();
+
+    assert_eq!(cpu.checked_read_u16(0x2002), None);
+    assert_eq!(cpu.checked_read_u16(0x2001), None);
+    assert_eq!(cpu.checked_read_u16(0xFFFC), Some(0x8000));
+}
+
+#[test]
+fn test_mem_read_u16_goes_through_devices() {
+    let mut cpu = CPU::new();
+    cpu.map_io(0xFFFC..=0xFFFD, Box::new(ConstantDevice(0x90)));
+
+    assert_eq!(cpu.mem_read_u16(0xFFFC), 0x9090);
+}
+
+#[test]
+fn test_reset_reads_mapped_vector() {
+    // a whole-cartridge device whose vector points at 0xC000
+    struct Cartridge;
+    impl IoDevice for Cartridge {
+        fn read(&mut self, addr: u16) -> u8 {
+            match addr {
+                0xFFFD => 0xC0,
+                _ => 0x00,
+            }
+        }
+
+        fn write(&mut self, _addr: u16, _data: u8) {}
+    }
+
+    let mut cpu = CPU::new();
+    cpu.map_io(0x8000..=0xFFFF, Box::new(Cartridge));
+    cpu.reset();
+
+    assert_eq!(cpu.program_counter, 0xC000);
+    // the peek still sees the flat memory underneath
+    assert_eq!(cpu.reset_vector(), 0x0000);
+}
+
+#[test]
+fn test_custom_reset_vector() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x00]).unwrap();
+    assert_eq!(cpu.reset_vector(), 0x8000);
+
+    cpu.set_reset_vector(0xC000);
+    cpu.reset();
+
+    assert_eq!(cpu.reset_vector(), 0xC000);
+    assert_eq!(cpu.program_counter, 0xC000);
+}
+
+#[test]
+fn test_opcode_categories() {
+    let lda = OpCode::lookup(0xA9).unwrap();
+    assert!(lda.is_official());
+    assert_eq!(lda.category, OpCodeCategory::Official);
+
+    let lax = OpCode::lookup(0xA7).unwrap();
+    assert!(!lax.is_official());
+    assert_eq!(lax.category, OpCodeCategory::Illegal);
+
+    assert_eq!(OpCode::lookup(0x02).unwrap().category, OpCodeCategory::Jam);
+}
+
+#[test]
+fn test_lax() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x10, 0x99);
+    cpu.load_and_run(vec![0xA7, 0x10, 0x00]);
+
+    assert_eq!(cpu.register_a, 0x99);
+    assert_eq!(cpu.register_x, 0x99);
+    assert!(cpu.negative());
+}
+
+#[test]
+fn test_step_back() {
+    let mut cpu = CpuBuilder::new().history(8).build();
+    cpu.load(vec![
+        0xA9, 0x05, // lda #$05
+        0x85, 0x10, // sta $10
+        0xE8, // inx
+        0x00, // brk
+    ])
+    .unwrap();
+    cpu.reset();
+
+    cpu.exec_one();
+    cpu.exec_one();
+    cpu.exec_one();
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(cpu.mem_read(0x10), 0x05);
+
+    assert!(cpu.step_back());
+    assert!(cpu.step_back());
+
+    // back to just after the lda
+    assert_eq!(cpu.program_counter, 0x8002);
+    assert_eq!(cpu.register_a, 0x05);
+    assert_eq!(cpu.register_x, 0);
+    assert_eq!(cpu.mem_read(0x10), 0x00);
+    assert_eq!(cpu.cycles, 2);
+}
+
+#[test]
+fn test_step_back_over_interrupt_entry() {
+    let mut cpu = CpuBuilder::new().history(8).build();
+    cpu.load_segments(&[(0x8000, &[0xE8, 0x00]), (0x9000, &[0x00])])
+        .unwrap();
+    cpu.mem_write_u16(0xFFFA, 0x9000);
+    cpu.mem_write_u16(0xFFFC, 0x8000);
+    cpu.reset();
+    cpu.trigger_nmi();
+
+    cpu.exec_one();
+    assert_eq!(cpu.program_counter, 0x9000);
+
+    assert!(cpu.step_back());
+    assert_eq!(cpu.program_counter, 0x8000);
+
+    // the nmi is still pending, so the replay takes the same path
+    cpu.exec_one();
+    assert_eq!(cpu.program_counter, 0x9000);
+    assert_eq!(cpu.register_x, 0);
+}
+
+#[test]
+fn test_step_back_skips_disabled_opcode() {
+    let mut cpu = CpuBuilder::new().history(8).build();
+    cpu.load(vec![0xE8, 0x00]).unwrap();
+    cpu.reset();
+    cpu.disable_opcode(0xE8);
+
+    assert!(cpu.try_exec_one().is_err());
+    assert!(!cpu.step_back());
+}
+
+#[test]
+fn test_asl_accumulator_vs_absolute_cycles() {
+    let mut accumulator = CPU::new();
+    accumulator.load_and_run(vec![0x0A, 0x00]);
+
+    let mut absolute = CPU::new();
+    absolute.load_and_run(vec![0x0E, 0x00, 0x02, 0x00]);
+
+    assert_eq!(accumulator.cycles, 2);
+    assert_eq!(absolute.cycles, 6);
+}
+
+#[test]
+fn test_load_segments() {
+    let mut cpu = CPU::new();
+    cpu.load_segments(&[(0x8000, &[0xA5, 0x10, 0x00]), (0x0010, &[0x42, 0x43])])
+        .unwrap();
+
+    assert_eq!(cpu.mem_read(0x8000), 0xA5);
+    assert_eq!(cpu.mem_read(0x8002), 0x00);
+    assert_eq!(cpu.mem_read(0x0010), 0x42);
+    assert_eq!(cpu.mem_read(0x0011), 0x43);
+    assert_eq!(cpu.reset_vector(), 0x0000);
+
+    assert_eq!(
+        cpu.load_segments(&[(0x0300, &[0x01]), (0xFFFF, &[0x01, 0x02])]),
+        Err(LoadError::OutOfBounds {
+            start: 0xFFFF,
+            len: 2
+        })
+    );
+    assert_eq!(cpu.mem_read(0x0300), 0x00);
+}
+
+#[test]
+fn test_irq_leaves_decimal_flag_alone() {
+    let mut cpu = CPU::new();
+    cpu.load_segments(&[
+        (0x8000, &[0xF8, 0x58]), // sed, cli
+        (0x9000, &[0xE8, 0x00]), // handler: inx, brk
+        (0xFFFE, &[0x00, 0x90]), // irq vector
+    ])
+    .unwrap();
+    cpu.set_reset_vector(0x8000);
+    cpu.reset();
+    cpu.exec_one();
+    cpu.exec_one();
+
+    cpu.irq();
+
+    assert_eq!(cpu.program_counter, 0x9000);
+    assert!(cpu.decimal());
+    assert!(cpu.interrupt_disable());
+    // the status pushed for the handler has D set but not B
+    assert_eq!(cpu.mem_read(0x01FB), 0b0010_1000);
+    // return address
+    assert_eq!(cpu.mem_read_u16(0x01FC), 0x8002);
+
+    cpu.run();
+    assert!(cpu.decimal());
+    assert_eq!(cpu.register_x, 1);
+}
+
+#[test]
+fn test_stack_placement() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x42, 0x48, 0x00]).unwrap(); // lda #$42, pha
+    cpu.reset();
+    let initial_sp = cpu.stack_pointer;
+    cpu.run();
+
+    assert_eq!(STACK_BASE, 0x0100);
+    assert_eq!(cpu.mem_read(0x0100 + initial_sp as u16), 0x42);
+    assert_eq!(cpu.stack_pointer, initial_sp - 1);
+}
+
+#[test]
+fn test_ram_execute_hook() {
+    let fetches = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&fetches);
+    let mut cpu = CpuBuilder::new()
+        .on_ram_execute(Box::new(move |pc| log.borrow_mut().push(pc)))
+        .build();
+    cpu.mem_write(0x0010, 0xE8); // inx
+    cpu.mem_write(0x0011, 0x00); // brk
+    cpu.load_and_run(vec![
+        0x4C, // jmp
+        0x10, 0x00, // $0010
+    ]);
+
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(*fetches.borrow(), vec![0x0010, 0x0011]);
+}
+
+#[test]
+fn test_with_memory_uses_external_buffer() {
+    let mut ram = vec![0u8; 0x10000];
+    {
+        let mut cpu = CPU::with_memory(&mut ram);
+        cpu.load_and_run(vec![
+            0xA9, 0x77, // lda #$77
+            0x85, 0x10, // sta $10
+            0x00,
+        ]);
+        assert_eq!(cpu.register_a, 0x77);
+    }
+
+    assert_eq!(ram[0x0010], 0x77);
+    assert_eq!(&ram[0x8000..0x8002], &[0xA9, 0x77]);
+}
+
+#[test]
+fn test_disassemble_indirect_modes() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA1, 0x04, // lda ($04,X)
+        0xB1, 0x0A, // lda ($0A),Y
+        0x81, 0xFF, // sta ($FF,X)
+    ])
+    .unwrap();
+
+    assert_eq!(cpu.disassemble(0x8000).unwrap(), "LDA ($04,X)");
+    assert_eq!(cpu.disassemble(0x8002).unwrap(), "LDA ($0A),Y");
+    assert_eq!(cpu.disassemble(0x8004).unwrap(), "STA ($FF,X)");
+}
+
+#[test]
+fn test_power_on_pattern() {
+    let mut cpu = CpuBuilder::new().power_on_pattern(Pattern::AllFF).build();
+    assert_eq!(cpu.mem_read(0x0000), 0xFF);
+    assert_eq!(cpu.mem_read(0x00FF), 0xFF);
+    assert_eq!(cpu.mem_read(0x07FF), 0xFF);
+
+    let mut cpu = CpuBuilder::new()
+        .power_on_pattern(Pattern::Alternating)
+        .build();
+    assert_eq!(cpu.mem_read(0x0010), 0x00);
+    assert_eq!(cpu.mem_read(0x0011), 0xFF);
+}
+
+#[test]
+fn test_absolute_x_wraps_at_address_space_end() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x0001, 0x5A);
+    cpu.load_and_run(vec![
+        0xA9, 0x02, // lda #$02
+        0xAA, // tax
+        0xBD, 0xFF, 0xFF, // lda $FFFF,X
+        0x00,
+    ]);
+
+    assert_eq!(cpu.register_a, 0x5A);
+}
+
+#[test]
+fn test_opcode_len_matches_mode() {
+    for code in 0..=255u8 {
+        let Some(opcode) = OpCode::lookup(code) else {
+            continue;
+        };
+        let expected = match opcode.mode {
+            AddressingMode::NoneAddressing => 1,
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPage_X
+            | AddressingMode::ZeroPage_Y
+            | AddressingMode::Indirect_X
+            | AddressingMode::Indirect_Y
+            | AddressingMode::Relative => 2,
+            AddressingMode::Absolute | AddressingMode::Absolute_X | AddressingMode::Absolute_Y => 3,
+        };
+        assert_eq!(
+            opcode.len, expected,
+            "{:02X} {} has length {} but {:?} needs {}",
+            code, opcode.mnemonic, opcode.len, opcode.mode, expected
+        );
+    }
+}
+
+#[test]
+fn test_run_until_write() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA9, 0x80, // lda #$80
+        0x8D, 0x00, 0x60, // sta $6000
+        0x4C, 0x05, 0x80, // jmp $8005, spin forever
+    ])
+    .unwrap();
+    cpu.reset();
+
+    assert!(cpu.run_until_write(0x6000, 0x80, 1000));
+    assert_eq!(cpu.program_counter, 0x8005);
+    assert_eq!(cpu.state, RunState::Paused);
+
+    // nothing else gets written, so the budget runs out
+    assert!(!cpu.run_until_write(0x6000, 0x01, 30));
+    assert!(cpu.cycles >= 6 + 30);
+}
+
+#[test]
+fn test_stack_slice() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![
+        0x20, 0x04, 0x80, // jsr $8004
+        0x00, //
+        0x20, 0x08, 0x80, // jsr $8008
+        0x00, //
+        0x00, // brk, two calls deep
+    ]);
+
+    // innermost call first, each return address little-endian, then the
+    // two bytes reset leaves below SP=0xFD
+    assert_eq!(cpu.stack_slice(), &[0x06, 0x80, 0x02, 0x80, 0x00, 0x00]);
+
+    cpu.reset();
+    cpu.stack_pointer = 0xFF;
+    assert!(cpu.stack_slice().is_empty());
+}
+
+#[test]
+fn test_opcode_hook_fires_per_execution() {
+    let count = Rc::new(RefCell::new(0));
+    let counter = Rc::clone(&count);
+    let mut cpu = CPU::new();
+    cpu.on_opcode(
+        0xE8,
+        Box::new(move |cpu| {
+            assert_eq!(cpu.mem_peek(cpu.program_counter), 0xE8);
+            *counter.borrow_mut() += 1;
+        }),
+    );
+    cpu.load_and_run(vec![
+        0xE8, 0xE8, // inx, inx
+        0xAA, // tax
+        0xE8, // inx
+        0x00,
+    ]);
+
+    assert_eq!(*count.borrow(), 3);
+    assert_eq!(cpu.register_x, 1);
+}
+
+// time only moves when something sleeps
+#[cfg(feature = "std")]
+struct MockClock {
+    now: Rc<RefCell<Duration>>,
+}
+
+#[cfg(feature = "std")]
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.borrow()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        *self.now.borrow_mut() += duration;
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_frame_limiter_sleeps_rest_of_frame() {
+    let now = Rc::new(RefCell::new(Duration::from_millis(100)));
+    let mut limiter = FrameLimiter::new(
+        MockClock {
+            now: Rc::clone(&now),
+        },
+        50.0,
+    );
+
+    // 20ms frames, finished after 5ms
+    let start = limiter.now();
+    *now.borrow_mut() += Duration::from_millis(5);
+    assert_eq!(limiter.wait(start), Duration::from_millis(15));
+    assert_eq!(*now.borrow(), Duration::from_millis(120));
+
+    // at double speed the frame budget halves, and an overrun doesn't sleep
+    limiter.set_speed(2.0);
+    let start = limiter.now();
+    *now.borrow_mut() += Duration::from_millis(4);
+    assert_eq!(limiter.remaining(start), Duration::from_millis(6));
+    *now.borrow_mut() += Duration::from_millis(10);
+    assert_eq!(limiter.wait(start), Duration::ZERO);
+}
+
+#[test]
+fn test_ram_snapshot_restore() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x0000, 0x11);
+    cpu.mem_write(0x00FF, 0x22);
+    cpu.mem_write(0x07FF, 0x33);
+    let ram = cpu.ram_snapshot();
+
+    cpu.mem_fill(0x0000, 0x800, 0xEE);
+    cpu.ram_restore(&ram);
+
+    assert_eq!(cpu.mem_read(0x0000), 0x11);
+    assert_eq!(cpu.mem_read(0x00FF), 0x22);
+    assert_eq!(cpu.mem_read(0x07FF), 0x33);
+    assert_eq!(cpu.mem_read(0x0001), 0x00);
+}
+
+#[test]
+fn test_sram_save_and_load() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![
+        0xA9, 0x42, // lda #$42
+        0x8D, 0x00, 0x60, // sta $6000
+        0x8D, 0xFF, 0x7F, // sta $7FFF
+        0x00,
+    ]);
+
+    let save = cpu.save_sram().to_vec();
+    assert_eq!(save.len(), 0x2000);
+    assert_eq!((save[0], save[1], save[0x1FFF]), (0x42, 0x00, 0x42));
+
+    let mut cpu = CPU::new();
+    cpu.load_sram(&save).unwrap();
+    assert_eq!(cpu.mem_read(0x6000), 0x42);
+    assert!(matches!(
+        cpu.load_sram(&[0; 0x2001]),
+        Err(LoadError::TooLarge { .. })
+    ));
+}
+
+#[test]
+fn test_triggered_irq_runs_handler() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0x58, // cli
+        0xE8, // inx
+        0xE8, // inx
+        0x00,
+    ])
+    .unwrap();
+    let handler: &[u8] = &[
+        0xA9, 0xAA, // lda #$AA
+        0x85, 0x10, // sta $10
+        0x40, // rti
+    ];
+    cpu.load_segments(&[(0x9000, handler), (0xFFFE, &[0x00, 0x90])])
+        .unwrap();
+    cpu.reset();
+
+    let mut handler_entries = 0;
+    let mut triggered = false;
+    cpu.run_with_callback(|cpu| {
+        if cpu.program_counter == 0x9000 {
+            handler_entries += 1;
+        }
+        if cpu.register_x == 1 && !triggered {
+            cpu.trigger_irq();
+            triggered = true;
+        }
+    });
+
+    assert_eq!(cpu.mem_read(0x10), 0xAA);
+    assert_eq!(handler_entries, 1);
+    assert_eq!(cpu.register_x, 2);
+    assert!(!cpu.interrupt_disable());
+}
+
+#[test]
+fn test_mem_write_u16_is_little_endian() {
+    let mut cpu = CPU::new();
+    cpu.mem_write_u16(0x0200, 0xBEEF);
+    assert_eq!(cpu.mem_peek(0x0200), 0xEF);
+    assert_eq!(cpu.mem_peek(0x0201), 0xBE);
+
+    // the high byte wraps around rather than overflowing
+    cpu.mem_write_u16(0xFFFF, 0x1234);
+    assert_eq!(cpu.mem_peek(0xFFFF), 0x34);
+    assert_eq!(cpu.mem_peek(0x0000), 0x12);
+    assert_eq!(cpu.mem_read_u16(0xFFFF), 0x1234);
+}
+
+#[test]
+fn test_indexed_modes_use_their_own_register() {
+    // runs a single instruction with X=1 and Y=2
+    fn run_indexed(program: Vec<u8>) -> CPU<'static> {
+        let mut cpu = CPU::new();
+        for (addr, value) in [
+            (0x0000, 0x01),
+            (0x0011, 0x11),
+            (0x0012, 0x12),
+            (0x0301, 0x31),
+            (0x0302, 0x32),
+        ] {
+            cpu.mem_write(addr, value);
+        }
+        cpu.load(program).unwrap();
+        cpu.reset();
+        cpu.register_x = 1;
+        cpu.register_y = 2;
+        cpu.exec_one();
+        cpu
+    }
+
+    assert_eq!(run_indexed(vec![0xB5, 0x10]).register_a, 0x11); // lda $10,X
+    assert_eq!(run_indexed(vec![0xB7, 0x10]).register_a, 0x12); // lax $10,Y
+    assert_eq!(run_indexed(vec![0xBD, 0x00, 0x03]).register_a, 0x31); // lda $0300,X
+    assert_eq!(run_indexed(vec![0xB9, 0x00, 0x03]).register_a, 0x32); // lda $0300,Y
+
+    // zero page indexing stays in the zero page
+    assert_eq!(run_indexed(vec![0xB5, 0xFF]).register_a, 0x01); // lda $FF,X
+}
+
+#[test]
+fn test_from_state() {
+    let mut cpu = CPU::from_state(CpuState {
+        register_a: 0x10,
+        register_x: 0x41,
+        status: CPUFlags::CARRY | CPUFlags::BREAK2,
+        stack_pointer: 0xF0,
+        program_counter: 0xC000,
+        memory: vec![(0xC000, vec![0xE8, 0x00])], // inx, brk
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert!(cpu.exec_one());
+    assert_eq!(cpu.register_x, 0x42);
+    assert_eq!(cpu.register_a, 0x10);
+    assert_eq!(cpu.program_counter, 0xC001);
+    assert_eq!(cpu.stack_pointer, 0xF0);
+    assert!(cpu.carry());
+}
+
+#[test]
+fn test_cycles_for_branch() {
+    let bne = OpCode::lookup(0xD0).unwrap();
+    assert_eq!(bne.cycles_for(false, false), 2);
+    assert_eq!(bne.cycles_for(true, false), 2);
+    assert_eq!(bne.cycles_for(false, true), 3);
+    assert_eq!(bne.cycles_for(true, true), 4);
+
+    // 256 times round inx; bne -3, the branch is taken all but the last time
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![0xE8, 0xD0, 0xFD, 0x00]);
+    assert_eq!(cpu.register_x, 0);
+    assert_eq!(cpu.cycles, 256 * 2 + 255 * 3 + 2);
+}
+
+#[test]
+fn test_page_cross_penalty_only_on_indexed_modes() {
+    for code in 0..=0xFF {
+        let Some(opcode) = OpCode::lookup(code) else {
+            continue;
+        };
+        if opcode.page_cross_penalty {
+            assert!(
+                matches!(
+                    opcode.mode,
+                    AddressingMode::Absolute_X
+                        | AddressingMode::Absolute_Y
+                        | AddressingMode::Indirect_Y
+                ),
+                "{:02X} {}",
+                code,
+                opcode.mnemonic
+            );
+        }
+    }
+}
+
+#[test]
+fn test_cycles_for_indexed_read() {
+    let lda = OpCode::lookup(0xBD).unwrap(); // lda abs,X
+    assert_eq!(lda.cycles_for(false, false), 4);
+    assert_eq!(lda.cycles_for(true, false), 5);
+
+    // stores always take the extra cycle
+    let sta = OpCode::lookup(0x9D).unwrap(); // sta abs,X
+    assert_eq!(sta.cycles_for(false, false), 5);
+    assert_eq!(sta.cycles_for(true, false), 5);
+
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xBD, 0xFF, 0x02, 0xBD, 0x00, 0x02]).unwrap(); // lda $02FF,X; lda $0200,X
+    cpu.reset();
+    cpu.register_x = 1;
+    cpu.exec_one();
+    assert_eq!(cpu.cycles, 5);
+    cpu.exec_one();
+    assert_eq!(cpu.cycles, 9);
+}
+
+#[test]
+fn test_opcode_handler_overrides_builtin() {
+    let mut cpu = CPU::new();
+    cpu.set_opcode_handler(
+        0xEA,
+        Box::new(|cpu, mode| {
+            assert_eq!(*mode, AddressingMode::NoneAddressing);
+            cpu.mem_write(0x0010, 0x5E);
+        }),
+    );
+    cpu.load_and_run(vec![0xEA, 0xE8, 0x00]); // nop, inx
+
+    assert_eq!(cpu.mem_read(0x0010), 0x5E);
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(cpu.cycles, 4);
+}
+
+#[test]
+fn test_halt_from_callback() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xE8, 0xE8, 0xE8, 0xE8, 0xE8, 0x00]).unwrap();
+    cpu.reset();
+
+    let mut executed = 0;
+    let outcome = cpu.run_with_callback(|cpu| {
+        if executed == 3 {
+            cpu.halt_requested = true;
+        }
+        executed += 1;
+    });
+
+    assert_eq!(outcome, RunOutcome::Halted);
+    assert_eq!(cpu.register_x, 3);
+    assert_eq!(cpu.program_counter, 0x8003);
+    assert!(!cpu.halt_requested);
+
+    // resuming runs the rest of the program
+    assert_eq!(cpu.run_with_callback(|_| {}), RunOutcome::Stopped);
+    assert_eq!(cpu.register_x, 5);
+}
+
+#[test]
+fn test_nmi_returns_to_instruction_after() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA9, 0x01, // lda #$01
+        0x8D, 0x00, 0x02, // sta $0200, NMI raised while this runs
+        0xE8, // inx
+        0x00,
+    ])
+    .unwrap();
+    cpu.load_segments(&[(0x9000, &[0x00]), (0xFFFA, &[0x00, 0x90])])
+        .unwrap();
+    cpu.reset();
+    cpu.on_opcode(0x8D, Box::new(|cpu| cpu.trigger_nmi()));
+
+    cpu.run();
+
+    // the store finished, then the NMI was taken before the inx
+    assert_eq!(cpu.program_counter, 0x9001);
+    assert_eq!(cpu.mem_read(0x0200), 0x01);
+    assert_eq!(cpu.register_x, 0);
+    assert_eq!(&cpu.stack_slice()[1..3], &[0x05, 0x80]);
+}
+
+#[test]
+fn test_opcodes_for_mnemonic() {
+    let lda = opcodes_for_mnemonic("LDA");
+    assert_eq!(lda.len(), 8);
+    for (i, a) in lda.iter().enumerate() {
+        assert_eq!(a.mnemonic, "LDA");
+        assert!(lda[i + 1..].iter().all(|b| a.mode != b.mode));
+    }
+
+    assert_eq!(opcodes_for_mnemonic("inx").len(), 1);
+    assert!(opcodes_for_mnemonic("XYZ").is_empty());
+}
+
+#[test]
+fn test_flag_change_log() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA9, 0x50, // lda #$50
+        0x69, 0x50, // adc #$50, overflows into the sign bit
+        0x69, 0x60, // adc #$60, carries out leaving zero
+        0x00,
+    ])
+    .unwrap();
+    cpu.reset();
+
+    let mut log = Vec::new();
+    cpu.run_with_flag_log(|line| log.push(line));
+
+    assert_eq!(
+        log,
+        vec![
+            "ADC at $8002: OVERFLOW set, NEGATIV set",
+            "ADC at $8004: CARRY set, ZERO set, OVERFLOW cleared, NEGATIV cleared",
+        ]
+    );
+    assert_eq!(
+        describe_flag_changes(CPUFlags::ZERO, CPUFlags::ZERO | CPUFlags::CARRY).unwrap(),
+        "CARRY set"
+    );
+}
+
+#[test]
+fn test_preview_operand() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x0042, 0x99);
+    cpu.mem_write_u16(0x0010, 0x0040);
+    cpu.load(vec![
+        0xAD, 0x42, 0x00, // lda $0042
+        0xB1, 0x10, // lda ($10),Y
+        0xE8, // inx
+    ])
+    .unwrap();
+    cpu.reset();
+    cpu.register_y = 2;
+
+    assert_eq!(cpu.preview_operand(0x8000), Some((0x0042, 0x99)));
+    assert_eq!(cpu.preview_operand(0x8003), Some((0x0042, 0x99)));
+    assert_eq!(cpu.preview_operand(0x8005), None);
+    assert_eq!(cpu.register_a, 0);
+}
+
+#[test]
+fn test_plp_drops_break_and_forces_bit_5() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![
+        0xA9, 0x00, // lda #$00
+        0x48, // pha
+        0x28, // plp
+        0x00,
+    ]);
+    assert_eq!(cpu.status.bits(), 0x20);
+
+    // and nothing else is masked off
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![0xA9, 0xFF, 0x48, 0x28, 0x00]);
+    assert_eq!(cpu.status.bits(), 0xEF);
+}
+
+#[test]
+fn test_trace_matches_hand_written_log() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x05, 0x8D, 0x00, 0x02, 0xAA, 0xE8, 0x00])
+        .unwrap();
+    cpu.reset();
+
+    assert_trace_matches(
+        &mut cpu,
+        &[
+            "8000  A9 05     LDA #$05      A:00 X:00 Y:00 P:24 SP:FD",
+            "8002  8D 00 02  STA $0200     A:05 X:00 Y:00 P:24 SP:FD",
+            "8005  AA        TAX           A:05 X:00 Y:00 P:24 SP:FD",
+            "8006  E8        INX           A:05 X:05 Y:00 P:24 SP:FD",
+            "8007  00        BRK           A:05 X:06 Y:00 P:24 SP:FD",
+        ],
+    );
+}
+
+#[test]
+#[should_panic(expected = "trace diverges at line 2")]
+fn test_trace_mismatch_reports_line() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x05, 0xE8, 0x00]).unwrap();
+    cpu.reset();
+
+    assert_trace_matches(
+        &mut cpu,
+        &[
+            "8000  A9 05     LDA #$05      A:00 X:00 Y:00 P:24 SP:FD",
+            "8002  E8        INX           A:05 X:01 Y:00 P:24 SP:FD",
+        ],
+    );
+}
+
+#[test]
+fn test_trap_on_uninitialized_execute() {
+    let traps = Rc::new(RefCell::new(Vec::new()));
+    let log = Rc::clone(&traps);
+    let mut cpu = CpuBuilder::new()
+        .trap_uninitialized_execute(Box::new(move |pc| log.borrow_mut().push(pc)))
+        .build();
+    cpu.load_and_run(vec![
+        0xA9, 0xE8, // lda #$E8
+        0x85, 0x10, // sta $10, an inx the program wrote itself
+        0x4C, 0x10, 0x00, // jmp $0010
+    ]);
+
+    // $0010 was written but execution ran on into the untouched $0011
+    assert_eq!(*traps.borrow(), vec![0x0011]);
+    assert_eq!(cpu.register_x, 1);
+}
+
+#[test]
+fn test_sbc_16_bit_subtraction() {
+    // ($01,$00) = hi:lo - value, carry chained from the low byte into the high
+    fn subtract(lhs: u16, rhs: u16) -> (u16, bool) {
+        let [lhs_lo, lhs_hi] = lhs.to_le_bytes();
+        let [rhs_lo, rhs_hi] = rhs.to_le_bytes();
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0x38, // sec
+            0xA9, lhs_lo, // lda #lo
+            0xE9, rhs_lo, // sbc #lo
+            0x85, 0x00, // sta $00
+            0xA9, lhs_hi, // lda #hi
+            0xE9, rhs_hi, // sbc #hi
+            0x85, 0x01, // sta $01
+            0x00,
+        ]);
+        (cpu.mem_read_u16(0x0000), cpu.carry())
+    }
+
+    assert_eq!(subtract(0x1234, 0x0567), (0x0CCD, true));
+    assert_eq!(subtract(0x0100, 0x0001), (0x00FF, true));
+    assert_eq!(subtract(0x0100, 0x0200), (0xFF00, false));
+    assert_eq!(subtract(0x8000, 0x8000), (0x0000, true));
+}
+
+#[test]
+fn test_decimal_sbc() {
+    let mut cpu = CpuBuilder::new().decimal_mode_supported(true).build();
+    cpu.load_and_run(vec![
+        0xF8, // sed
+        0x38, // sec
+        0xA9, 0x42, // lda #$42
+        0xE9, 0x15, // sbc #$15
+        0x00,
+    ]);
+    assert_eq!(cpu.register_a, 0x27);
+    assert!(cpu.carry());
+
+    let mut cpu = CpuBuilder::new().decimal_mode_supported(true).build();
+    cpu.load_and_run(vec![0xF8, 0x38, 0xA9, 0x10, 0xE9, 0x20, 0x00]);
+    assert_eq!(cpu.register_a, 0x90);
+    assert!(!cpu.carry());
+}
+
+#[test]
+fn test_random_power_on_pattern_is_reproducible() {
+    let seeded = || {
+        CpuBuilder::new()
+            .rng(Box::new(Lcg::new(42)))
+            .power_on_pattern(Pattern::Random)
+            .build()
+    };
+
+    // power-on RAM takes the first bytes from the RNG, the register the rest
+    let mut cpu = seeded();
+    let mut again = seeded();
+    assert_eq!(&cpu.ram_snapshot()[..3], &[0x40, 0x16, 0x93]);
+    assert_eq!(cpu.ram_snapshot(), again.ram_snapshot());
+    assert_eq!(cpu.mem_read(0x00FE), again.mem_read(0x00FE));
+
+    // without an RNG the default seed is used, so the pattern is still fixed
+    let a = CpuBuilder::new().power_on_pattern(Pattern::Random).build();
+    let b = CpuBuilder::new().power_on_pattern(Pattern::Random).build();
+    assert_eq!(a.ram_snapshot(), b.ram_snapshot());
+    assert_ne!(a.ram_snapshot(), [0; 0x800]);
+}
+
+#[test]
+fn test_read_only_mode_drops_writes() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x0200, 0x11);
+    cpu.load(vec![0xA9, 0x42, 0x8D, 0x00, 0x02, 0x00]).unwrap(); // lda #$42; sta $0200
+    cpu.reset();
+    cpu.read_only = true;
+    cpu.run();
+
+    assert_eq!(cpu.register_a, 0x42);
+    assert_eq!(cpu.mem_read(0x0200), 0x11);
+}
+
+#[test]
+fn test_interrupt_after_taken_branch_timing() {
+    // raises an NMI while the branch at start executes, returns the cycle
+    // count when the handler is entered and X at that point
+    fn nmi_during_branch(start: u16, offset: u8) -> (usize, u8) {
+        let next = start + 2;
+        let target = next.wrapping_add(offset as i8 as u16);
+        let mut cpu = CPU::new();
+        cpu.load_segments(&[
+            (start, &[0xD0, offset]), // bne, taken as Z is clear
+            (next, &[0x00]),
+            (target, &[0xE8, 0x00]), // inx
+            (0x9000, &[0x00]),
+            (0xFFFA, &[0x00, 0x90]),
+        ])
+        .unwrap();
+        cpu.set_reset_vector(start);
+        cpu.reset();
+        cpu.on_opcode(0xD0, Box::new(|cpu| cpu.trigger_nmi()));
+
+        let mut entered = None;
+        cpu.run_with_callback(|cpu| {
+            if cpu.program_counter == 0x9000 && entered.is_none() {
+                entered = Some((cpu.cycles, cpu.register_x));
+            }
+        });
+        entered.unwrap()
+    }
+
+    // 3 cycle branch on the same page: the inx still runs first
+    assert_eq!(nmi_during_branch(0x8000, 0x01), (3 + 2 + 7, 1));
+    // 4 cycle branch onto the next page: the NMI is taken straight away
+    assert_eq!(nmi_during_branch(0x80FC, 0x02), (4 + 7, 0));
+}
+
+#[test]
+fn test_execute() {
+    let cpu = CPU::execute(vec![0xA9, 0x05, 0x00]); // lda #$05
+    assert_eq!(cpu.register_a, 0x05);
+    assert_eq!(cpu.program_counter, 0x8003);
+}
+
+#[test]
+fn test_stack_wraps_within_page_one() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x00FF, 0x77);
+    cpu.load(vec![
+        0xA9, 0x11, // lda #$11
+        0x48, // pha, at SP=$00
+        0xA9, 0x22, // lda #$22
+        0x48, // pha, at SP=$FF
+        0x00,
+    ])
+    .unwrap();
+    cpu.reset();
+    cpu.stack_pointer = 0x00;
+    cpu.run();
+
+    assert_eq!(cpu.mem_read(0x0100), 0x11);
+    assert_eq!(cpu.mem_read(0x01FF), 0x22);
+    assert_eq!(cpu.mem_read(0x00FF), 0x77);
+    assert_eq!(cpu.stack_pointer, 0xFE);
+}
+
+fn store_ones() -> CPU<'static> {
+    CPU::execute(vec![
+        0xA9, 0x01, // lda #$01
+        0x8D, 0x00, 0x02, // sta $0200
+        0x8D, 0x01, 0x02, // sta $0201
+        0x00,
+    ])
+}
+
+#[test]
+fn test_assert_memory_eq() {
+    assert_memory_eq(
+        &store_ones(),
+        &[(0x0200, 0x01), (0x0201, 0x01), (0x0202, 0x00)],
+    );
+}
+
+#[test]
+#[should_panic(expected = "memory differs at 2 of 3 addresses
+  $0201: expected $02, found $01
+  $0202: expected $03, found $00")]
+fn test_assert_memory_eq_reports_every_difference() {
+    assert_memory_eq(
+        &store_ones(),
+        &[(0x0200, 0x01), (0x0201, 0x02), (0x0202, 0x03)],
+    );
+}
+
+#[test]
+fn test_self_modifying_code_in_ram() {
+    let mut cpu = CPU::new();
+    cpu.load_segments(&[(0x0300, &[0xA9, 0x11, 0x00])]).unwrap(); // lda #$11
+    cpu.load_and_run(vec![
+        0xA9, 0x42, // lda #$42
+        0x8D, 0x01, 0x03, // sta $0301, patching the operand
+        0xA9, 0x00, // lda #$00
+        0x4C, 0x00, 0x03, // jmp $0300
+    ]);
+
+    // the patched lda #$42 ran, not the original lda #$11
+    assert_eq!(cpu.register_a, 0x42);
+    assert_eq!(cpu.program_counter, 0x0303);
+}
+
+#[test]
+fn test_slo_shifts_memory_and_ors_into_a() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x0010, 0b1100_0001);
+    cpu.load_and_run(vec![
+        0xA9,
+        0b0000_0100, // lda
+        0x07,
+        0x10, // slo $10
+        0x00,
+    ]);
+
+    assert_eq!(cpu.mem_read(0x0010), 0b1000_0010);
+    assert_eq!(cpu.register_a, 0b1000_0110);
+    assert!(cpu.carry());
+    assert!(cpu.negative());
+    assert_eq!(cpu.cycles, 2 + 5);
+}
+
+#[test]
+fn test_rla_sre_rra() {
+    let run = |opcode: u8, a: u8, m: u8, carry: bool| {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0010, m);
+        cpu.load(vec![0xA9, a, opcode, 0x10, 0x00]).unwrap();
+        cpu.reset();
+        cpu.status.set(CPUFlags::CARRY, carry);
+        cpu.run();
+        (cpu.mem_peek(0x0010), cpu.register_a, cpu.carry())
+    };
+
+    // rol $10 then and: carry comes in at bit 0, bit 7 goes out
+    assert_eq!(run(0x27, 0xFF, 0b1000_0000, true), (0x01, 0x01, true));
+    // lsr $10 then eor
+    assert_eq!(run(0x47, 0xFF, 0b0000_0011, false), (0x01, 0xFE, true));
+    // ror $10 then adc: the rotated out carry is added in
+    assert_eq!(run(0x67, 0x10, 0b0000_0011, false), (0x01, 0x12, false));
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(
+        decode(0xA9),
+        Some(Instruction::Lda(AddressingMode::Immediate))
+    );
+    assert_eq!(
+        decode(0x91),
+        Some(Instruction::Sta(AddressingMode::Indirect_Y))
+    );
+    assert_eq!(
+        decode(0x0A),
+        Some(Instruction::Asl(AddressingMode::NoneAddressing))
+    );
+    assert_eq!(decode(0xD0), Some(Instruction::Bne));
+    assert_eq!(decode(0x00), Some(Instruction::Brk));
+    assert_eq!(
+        decode(0xB7),
+        Some(Instruction::Lax(AddressingMode::ZeroPage_Y))
+    );
+    assert_eq!(decode(0x02), Some(Instruction::Jam));
+    assert_eq!(decode(0xFF), None);
+
+    // everything in the table decodes to something
+    for code in 0..=255u8 {
+        assert_eq!(OpCode::lookup(code).is_some(), decode(code).is_some());
+    }
+}
+
+#[test]
+fn test_rmw_on_io_writes_twice() {
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    let mut cpu = CPU::new();
+    cpu.map_io(
+        0x4000..=0x4000,
+        Box::new(RecordingDevice {
+            value: 0x21,
+            writes: Rc::clone(&writes),
+        }),
+    );
+    cpu.mem_write(0x0010, 0x21);
+    cpu.load_and_run(vec![
+        0x0E, 0x00, 0x40, // asl $4000
+        0x06, 0x10, // asl $10, plain RAM
+        0x00,
+    ]);
+
+    // the old value goes back out before the shifted one
+    assert_eq!(*writes.borrow(), vec![0x21, 0x42]);
+    assert_eq!(cpu.mem_read(0x0010), 0x42);
+}
+
+#[test]
+fn test_run_with_callback_every() {
+    let mut program = vec![0xE8; 12]; // 12 x inx
+    program.push(0x00);
+
+    let mut cpu = CPU::new();
+    cpu.load(program).unwrap();
+    cpu.reset();
+    let mut seen = Vec::new();
+    cpu.run_with_callback_every(4, |cpu| seen.push(cpu.register_x));
+
+    assert_eq!(seen, vec![4, 8, 12]);
+}
+
+#[test]
+fn test_run_with_callback_every_instruction() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xE8, 0xE8, 0xE8, 0x00]).unwrap(); // 3 x inx, brk
+    cpu.reset();
+    let mut seen = Vec::new();
+    cpu.run_with_callback_every(1, |cpu| seen.push(cpu.register_x));
+
+    // once after each of the four instructions, brk included
+    assert_eq!(seen, vec![1, 2, 3, 3]);
+
+    cpu.reset();
+    let mut calls = 0;
+    cpu.run_with_callback_every(2, |_| calls += 1);
+    assert_eq!(calls, 2);
+}
+
+#[test]
+fn test_flags_as_nestest_byte() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x00]).unwrap();
+    cpu.reset();
+    assert_eq!(cpu.status.as_nestest_byte(), 0x24);
+
+    assert_eq!(CPUFlags::BREAK.as_nestest_byte(), 0x20);
+    assert_eq!(CPUFlags::all().as_nestest_byte(), 0xEF);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_export_listing() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA9, 0x05, // lda #$05
+        0x8D, 0x00, 0x02, // sta $0200
+        0xFF, // not an opcode
+        0xD0, 0xF8, // bne $8000
+        0xE8, // inx, past the end of the region
+    ])
+    .unwrap();
+
+    let mut out = Vec::new();
+    cpu.export_listing(0x8000, 0x8006, &mut out).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "8000  A9 05     LDA #$05\n\
+         8002  8D 00 02  STA $0200\n\
+         8005  FF        .byte $FF\n\
+         8006  D0 F8     BNE $8000\n"
+    );
+}
+
+#[test]
+fn test_adc_consumes_carry_in() {
+    let cpu = CPU::execute(vec![
+        0x38, // sec
+        0xA9, 0x00, // lda #$00
+        0x69, 0x00, // adc #$00
+        0x00,
+    ]);
+
+    assert_eq!(cpu.register_a, 0x01);
+    assert!(!cpu.carry());
+    assert!(!cpu.zero());
+}
+
+#[test]
+fn test_mode_at_pc() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x05, 0xFF]).unwrap(); // lda #$05
+    cpu.reset();
+    assert_eq!(cpu.mode_at_pc(), Some(&AddressingMode::Immediate));
+
+    cpu.program_counter = 0x8002;
+    assert_eq!(cpu.mode_at_pc(), None);
+}
+
+#[test]
+fn test_run_to_cycle_stops_before_overshooting() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA9, 0x01, // lda #$01, 2 cycles
+        0x8D, 0x00, 0x02, // sta $0200, 4 cycles
+        0xE8, // inx, 2 cycles
+        0x00,
+    ])
+    .unwrap();
+    cpu.reset();
+
+    // 5 falls in the middle of the sta
+    cpu.run_to_cycle(5);
+    assert_eq!(cpu.cycles, 2);
+    assert_eq!(cpu.program_counter, 0x8002);
+    assert_eq!(cpu.mem_read(0x0200), 0x00);
+
+    cpu.run_to_cycle(6);
+    assert_eq!(cpu.cycles, 6);
+    assert_eq!(cpu.program_counter, 0x8005);
+}
+
+#[test]
+fn test_next_instruction_cycles_predicts_branches() {
+    let mut cpu = CPU::new();
+    cpu.load_segments(&[(0x80FC, &[0xD0, 0x02])]).unwrap(); // bne onto the next page
+    cpu.program_counter = 0x80FC;
+
+    assert_eq!(cpu.next_instruction_cycles(), Some(4));
+    cpu.status.insert(CPUFlags::ZERO);
+    assert_eq!(cpu.next_instruction_cycles(), Some(2));
+
+    cpu.trigger_nmi();
+    assert_eq!(cpu.next_instruction_cycles(), Some(7));
+}
+
+#[test]
+fn test_pla_pops_in_reverse_push_order() {
+    let mut cpu = CPU::new();
+    cpu.load_and_run(vec![
+        0xA9, 0xAA, 0x48, // lda #$aa; pha
+        0xA9, 0xBB, 0x48, // lda #$bb; pha
+        0x68, 0x85, 0x10, // pla; sta $10
+        0x68, 0x85, 0x11, // pla; sta $11
+        0x00,
+    ]);
+
+    assert_memory_eq(&cpu, &[(0x10, 0xBB), (0x11, 0xAA)]);
+    assert!(cpu.status.contains(CPUFlags::NEGATIV));
+    assert_eq!(cpu.mem_peek(STACK_BASE | 0xFD), 0xAA);
+    assert_eq!(cpu.mem_peek(STACK_BASE | 0xFC), 0xBB);
+}
+
+#[test]
+fn test_disabled_opcode_is_an_error() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xE8, 0x00]).unwrap(); // inx
+    cpu.reset();
+    cpu.disable_opcode(0xE8);
+
+    assert_eq!(
+        cpu.try_exec_one(),
+        Err(ExecError::Disabled {
+            code: 0xE8,
+            addr: 0x8000
+        })
+    );
+    assert_eq!(cpu.register_x, 0);
+    assert_eq!(cpu.program_counter, 0x8000);
+}
+
+// Stands in for PPUSTATUS: reading returns the vblank bit and clears it
+// along with the address latch, the way the PPU does
+struct StatusRegister {
+    status: u8,
+    latch: Rc<RefCell<bool>>,
+}
+
+impl IoDevice for StatusRegister {
+    fn read(&mut self, _addr: u16) -> u8 {
+        let value = self.status;
+        self.status &= 0x7F;
+        *self.latch.borrow_mut() = false;
+        value
+    }
+
+    fn write(&mut self, _addr: u16, _data: u8) {}
+}
+
+#[test]
+fn test_indexed_read_reaches_io_register() {
+    let latch = Rc::new(RefCell::new(true));
+    let mut cpu = CPU::new();
+    cpu.map_io(
+        0x2002..=0x2002,
+        Box::new(StatusRegister {
+            status: 0x80,
+            latch: latch.clone(),
+        }),
+    );
+    cpu.load_and_run(vec![
+        0xE8, 0xE8, // x = 2
+        0xBD, 0x00, 0x20, // lda $2000,x
+        0xBD, 0x00, 0x20, // lda $2000,x
+        0x00,
+    ]);
+
+    // the first read saw vblank and cleared it, so the second one didn't
+    assert_eq!(cpu.register_a, 0x00);
+    assert!(!*latch.borrow());
+}
+
+// lda #$00; pha; plp; php, so bit 5 is pulled clear and then pushed again
+const STATUS_ROUND_TRIP: [u8; 6] = [0xA9, 0x00, 0x48, 0x28, 0x08, 0x00];
+
+#[test]
+fn test_unused_bit_always_set_after_round_trip() {
+    let mut cpu = CpuBuilder::new().build();
+    cpu.load_and_run(STATUS_ROUND_TRIP.to_vec());
+
+    assert_eq!(cpu.status, CPUFlags::BREAK2);
+    assert_eq!(cpu.mem_peek(STACK_BASE | 0xFD), 0b0011_0000);
+}
+
+#[test]
+fn test_unused_bit_kept_as_pulled_when_configured() {
+    let mut cpu = CpuBuilder::new().unused_bit_always_set(false).build();
+    cpu.load_and_run(STATUS_ROUND_TRIP.to_vec());
+
+    assert_eq!(cpu.status, CPUFlags::empty());
+    assert_eq!(cpu.mem_peek(STACK_BASE | 0xFD), 0b0001_0000);
+}
+
+#[test]
+fn test_clear_ram_leaves_rom_alone() {
+    let mut cpu = CpuBuilder::new().power_on_pattern(Pattern::AllFF).build();
+    cpu.mem_write(0x0200, 0x42);
+    cpu.mem_write(0x8000, 0xEA);
+
+    cpu.clear_ram();
+
+    assert!(cpu.ram_snapshot().iter().all(|&byte| byte == 0));
+    assert_eq!(cpu.mem_peek(0x8000), 0xEA);
+}
+
+#[test]
+fn test_mnemonic_lookup() {
+    assert_eq!(mnemonic(0x00), Some("BRK"));
+    assert_eq!(mnemonic(0xA9), Some("LDA"));
+    assert_eq!(mnemonic(0xFF), None);
+}
+
+#[test]
+fn test_hang_detector_fires_on_jmp_to_self() {
+    let hangs = Rc::new(RefCell::new(Vec::new()));
+    let seen = hangs.clone();
+    let mut cpu = CpuBuilder::new()
+        .detect_hangs(10, Box::new(move |pc| seen.borrow_mut().push(pc)))
+        .build();
+    cpu.load(vec![
+        0xE8, // inx
+        0x4C, 0x01, 0x80, // jmp $8001
+    ])
+    .unwrap();
+    cpu.reset();
+
+    // the inx and nine trips round the loop
+    for _ in 0..10 {
+        cpu.exec_one();
+    }
+    assert!(hangs.borrow().is_empty());
+
+    cpu.exec_one();
+    assert_eq!(*hangs.borrow(), vec![0x8001]);
+
+    // only reported once
+    for _ in 0..20 {
+        cpu.exec_one();
+    }
+    assert_eq!(hangs.borrow().len(), 1);
+}
+
+// header followed by banks filled with their index, PRG then CHR
+fn ines_image(header: [u8; 16], prg_banks: usize, chr_banks: usize) -> Vec<u8> {
+    let mut image = header.to_vec();
+    for bank in 0..prg_banks {
+        image.extend(std::iter::repeat_n(bank as u8, 0x4000));
+    }
+    for bank in 0..chr_banks {
+        image.extend(std::iter::repeat_n(0x80 | bank as u8, 0x2000));
+    }
+    image
+}
+
+#[test]
+fn test_rom_nes2_header() {
+    let header = [
+        b'N', b'E', b'S', 0x1A, // magic
+        2, 1,    // 2 PRG banks, 1 CHR bank
+        0x31, // mapper bits 0-3, vertical mirroring
+        0xA8, // mapper bits 4-7, NES 2.0
+        0x21, // submapper 2, mapper bits 8-11
+        0x00, 0x07, 0x00, 0, 0, 0, 0,
+    ];
+    let rom = Rom::from_bytes(&ines_image(header, 2, 1)).unwrap();
+
+    assert_eq!(rom.format, RomFormat::Nes2);
+    assert_eq!(rom.mapper, 0x1A3);
+    assert_eq!(rom.submapper, 2);
+    assert_eq!(rom.mirroring, Mirroring::Vertical);
+    assert_eq!(rom.prg_ram_size, 8 * 1024);
+    assert_eq!(rom.prg_rom.len(), 0x8000);
+    assert_eq!(rom.prg_rom[0x4000], 1);
+    assert_eq!(rom.chr_rom, vec![0x80; 0x2000]);
+}
+
+#[test]
+fn test_rom_ines1_ignores_extended_bytes() {
+    let header = [
+        b'N', b'E', b'S', 0x1A, 1, 0, 0x31, 0xA0, 0x21, 0x0F, 0x07, 0, 0, 0, 0, 0,
+    ];
+    let rom = Rom::from_bytes(&ines_image(header, 1, 0)).unwrap();
+
+    assert_eq!(rom.format, RomFormat::INes);
+    assert_eq!(rom.mapper, 0xA3);
+    assert_eq!(rom.submapper, 0);
+    assert_eq!(rom.prg_rom.len(), 0x4000);
+    assert!(rom.chr_rom.is_empty());
+}
+
+#[test]
+fn test_rom_too_short() {
+    assert_eq!(
+        Rom::from_bytes(b"NES\x1A\x01").err(),
+        Some(RomError::TooShort { len: 5 })
+    );
+}
+
+#[test]
+fn test_rom_bad_magic() {
+    let mut header = [0; 16];
+    header[..4].copy_from_slice(b"NES\x00");
+
+    assert_eq!(
+        Rom::from_bytes(&ines_image(header, 1, 0)).err(),
+        Some(RomError::BadMagic)
+    );
+}
+
+#[test]
+fn test_rom_more_prg_banks_than_file() {
+    let mut header = [0; 16];
+    header[..4].copy_from_slice(b"NES\x1A");
+    header[4] = 2;
+
+    assert_eq!(
+        Rom::from_bytes(&ines_image(header, 1, 0)).err(),
+        Some(RomError::Truncated {
+            expected: 16 + 0x8000,
+            len: 16 + 0x4000
+        })
+    );
+}
+
+#[test]
+fn test_load_rom_reports_info() {
+    let header = [
+        b'N', b'E', b'S', 0x1A, // magic
+        1, 1, // 16 KiB PRG, 8 KiB CHR
+        0x03, 0x00, // vertical mirroring, battery, mapper 0
+        0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut image = ines_image(header, 1, 1);
+    image[16] = 0xE8; // inx at the start of PRG
+    image[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0xC0]); // reset to 0xC000
+    let rom = Rom::from_bytes(&image).unwrap();
+
+    let mut cpu = CPU::new();
+    let info = cpu.load_rom(&rom).unwrap();
+    assert_eq!(info.mapper, 0);
+    assert_eq!(info.prg_rom_size, 0x4000);
+    assert_eq!(info.chr_rom_size, 0x2000);
+    assert_eq!(info.mirroring, Mirroring::Vertical);
+    assert!(info.battery);
+
+    // the bank shows up at both 0x8000 and 0xC000
+    assert_eq!(cpu.mem_peek(0x8000), 0xE8);
+    assert_eq!(cpu.mem_peek(0xC000), 0xE8);
+    cpu.reset();
+    assert_eq!(cpu.program_counter, 0xC000);
+}
+
+#[test]
+fn test_load_rom_rejects_other_mappers() {
+    let header = [
+        b'N', b'E', b'S', 0x1A, 1, 0, 0x40, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let rom = Rom::from_bytes(&ines_image(header, 1, 0)).unwrap();
+
+    assert_eq!(
+        CPU::new().load_rom(&rom),
+        Err(LoadError::UnsupportedMapper { mapper: 4 })
+    );
+}
+
+// UxROM image with four PRG banks, each filled with its own index
+fn uxrom_image() -> Vec<u8> {
+    let header = [
+        b'N', b'E', b'S', 0x1A, 4, 0, 0x20, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    ines_image(header, 4, 0)
+}
+
+#[test]
+fn test_uxrom_bank_switching() {
+    let mut uxrom = Uxrom::new(&Rom::from_bytes(&uxrom_image()).unwrap());
+    assert_eq!(uxrom.read(0x8000), 0);
+
+    uxrom.write(0x8000, 2);
+    assert_eq!(uxrom.read(0x8000), 2);
+    assert_eq!(uxrom.read(0xBFFF), 2);
+    assert_eq!(uxrom.read(0xC000), 3);
+    // CHR RAM on a board without CHR ROM
+    assert_eq!(uxrom.chr_read(0x0000), 0);
+}
+
+#[test]
+fn test_load_rom_boots_uxrom() {
+    let mut image = uxrom_image();
+    let last_bank = 16 + 3 * 0x4000;
+    image[last_bank..last_bank + 9].copy_from_slice(&[
+        0xA9, 0x02, // lda #$02
+        0x8D, 0x00, 0x80, // sta $8000, select bank 2
+        0xAD, 0x00, 0x80, // lda $8000
+        0x00, // brk
+    ]);
+    image[last_bank + 0x3FFC..last_bank + 0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+
+    let mut cpu = CPU::new();
+    let info = cpu.load_rom(&Rom::from_bytes(&image).unwrap()).unwrap();
+    assert_eq!(info.mapper, 2);
+
+    cpu.reset();
+    assert_eq!(cpu.program_counter, 0xC000);
+    cpu.run();
+    assert_eq!(cpu.register_a, 2);
+}
+
+#[test]
+fn test_cnrom_chr_bank_switching() {
+    let header = [
+        b'N', b'E', b'S', 0x1A, 1, 2, 0x30, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut cnrom = Cnrom::new(&Rom::from_bytes(&ines_image(header, 1, 2)).unwrap());
+    assert_eq!(cnrom.chr_read(0x0000), 0x80);
+
+    cnrom.write(0x8000, 0x01);
+    assert_eq!(cnrom.chr_read(0x0000), 0x81);
+    assert_eq!(cnrom.chr_read(0x1FFF), 0x81);
+
+    // PRG is untouched and mirrored
+    assert_eq!(cnrom.read(0x8000), 0x00);
+    assert_eq!(cnrom.read(0xC000), 0x00);
+}
+
+#[test]
+fn test_load_rom_reports_battery() {
+    let mut header = [
+        b'N', b'E', b'S', 0x1A, 1, 0, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut cpu = CPU::new();
+    assert!(!cpu.has_battery());
+
+    cpu.load_rom(&Rom::from_bytes(&ines_image(header, 1, 0)).unwrap())
+        .unwrap();
+    assert!(!cpu.has_battery());
+
+    header[6] = 0x02;
+    cpu.load_rom(&Rom::from_bytes(&ines_image(header, 1, 0)).unwrap())
+        .unwrap();
+    assert!(cpu.has_battery());
+}
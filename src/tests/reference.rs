@@ -0,0 +1,174 @@
+use crate::CPU::{
+    rng::{EmuRng, Lcg},
+    CPUFlags, Mem, CPU,
+};
+
+// Randomised comparison against a reference model
+//
+// The reference below is written straight from the 6502 datasheet
+// descriptions, deliberately not sharing any code with the CPU, and is run
+// on random register/flag/memory states for a single instruction at a time
+
+const ZP_OPERAND: u8 = 0x42;
+
+#[derive(Debug, PartialEq)]
+struct State {
+    a: u8,
+    p: u8,
+    mem: u8,
+}
+
+fn flag(p: u8, flag: CPUFlags) -> bool {
+    p & flag.bits() != 0
+}
+
+fn with_flag(p: u8, flag: CPUFlags, set: bool) -> u8 {
+    if set {
+        p | flag.bits()
+    } else {
+        p & !flag.bits()
+    }
+}
+
+fn with_zn(p: u8, result: u8) -> u8 {
+    let p = with_flag(p, CPUFlags::ZERO, result == 0);
+    with_flag(p, CPUFlags::NEGATIV, result & 0x80 != 0)
+}
+
+// expected state after executing code, given the value it reads as operand
+fn reference(code: u8, before: &State, operand: u8) -> State {
+    let State { a, p, mem } = *before;
+
+    match code {
+        // ADC
+        0x69 | 0x65 => {
+            let carry = flag(p, CPUFlags::CARRY) as i16;
+            let unsigned = a as u16 + operand as u16 + carry as u16;
+            let signed = a as i8 as i16 + operand as i8 as i16 + carry;
+            let result = unsigned as u8;
+
+            let p = with_flag(p, CPUFlags::CARRY, unsigned > 0xFF);
+            let p = with_flag(p, CPUFlags::OVERFLOW, !(-128..=127).contains(&signed));
+            State {
+                a: result,
+                p: with_zn(p, result),
+                mem,
+            }
+        }
+
+        // AND
+        0x29 | 0x25 => {
+            let result = a & operand;
+            State {
+                a: result,
+                p: with_zn(p, result),
+                mem,
+            }
+        }
+
+        // ASL A
+        0x0A => {
+            let result = a << 1;
+            let p = with_flag(p, CPUFlags::CARRY, a & 0x80 != 0);
+            State {
+                a: result,
+                p: with_zn(p, result),
+                mem,
+            }
+        }
+
+        // ASL zero page
+        0x06 => {
+            let result = mem << 1;
+            let p = with_flag(p, CPUFlags::CARRY, mem & 0x80 != 0);
+            State {
+                a,
+                p: with_zn(p, result),
+                mem: result,
+            }
+        }
+
+        // BIT zero page
+        0x24 => {
+            let p = with_flag(p, CPUFlags::ZERO, a & operand == 0);
+            let p = with_flag(p, CPUFlags::OVERFLOW, operand & 0x40 != 0);
+            let p = with_flag(p, CPUFlags::NEGATIV, operand & 0x80 != 0);
+            State { a, p, mem }
+        }
+
+        _ => unreachable!("no reference for {:02X}", code),
+    }
+}
+
+const OPCODES: [u8; 7] = [0x69, 0x65, 0x29, 0x25, 0x0A, 0x06, 0x24];
+
+#[test]
+fn test_arithmetic_and_logic_match_reference() {
+    let mut rng = Lcg::new(6502);
+
+    for _ in 0..10_000 {
+        let code = OPCODES[rng.next_u8() as usize % OPCODES.len()];
+        let before = State {
+            a: rng.next_u8(),
+            // bit 5 always reads as set
+            p: rng.next_u8() | CPUFlags::BREAK2.bits(),
+            mem: rng.next_u8(),
+        };
+        let immediate = rng.next_u8();
+
+        let mut cpu = CPU::new();
+        let is_immediate = matches!(code, 0x69 | 0x29);
+        let arg = if is_immediate { immediate } else { ZP_OPERAND };
+        cpu.load(vec![code, arg]).unwrap();
+        cpu.reset();
+        cpu.register_a = before.a;
+        cpu.status = CPUFlags::from_bits_truncate(before.p);
+        cpu.mem_write(ZP_OPERAND as u16, before.mem);
+        cpu.exec_one();
+
+        let operand = if is_immediate { immediate } else { before.mem };
+        let expected = reference(code, &before, operand);
+        let actual = State {
+            a: cpu.register_a,
+            p: cpu.status.bits(),
+            mem: cpu.mem_read(ZP_OPERAND as u16),
+        };
+
+        assert_eq!(actual, expected, "opcode {:02X} from {:02X?}", code, before);
+    }
+}
+
+// Every accumulator/operand/carry combination of ADC against plain integer
+// arithmetic, to lock down the carry and overflow derivations
+#[test]
+fn test_adc_exhaustive_carry_and_overflow() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x69, 0x00]).unwrap();
+
+    for a in 0..=0xFFu8 {
+        for operand in 0..=0xFFu8 {
+            for carry_in in [false, true] {
+                cpu.reset();
+                cpu.mem_write(0x8001, operand);
+                cpu.register_a = a;
+                cpu.status.set(CPUFlags::CARRY, carry_in);
+                cpu.exec_one();
+
+                let unsigned = a as u16 + operand as u16 + carry_in as u16;
+                let signed = a as i8 as i16 + operand as i8 as i16 + carry_in as i16;
+
+                let context = format!("{:02X} + {:02X} + {}", a, operand, carry_in as u8);
+                assert_eq!(cpu.register_a, unsigned as u8, "{}", context);
+                assert_eq!(cpu.carry(), unsigned > 0xFF, "{}", context);
+                assert_eq!(
+                    cpu.overflow(),
+                    !(-128..=127).contains(&signed),
+                    "{}",
+                    context
+                );
+                assert_eq!(cpu.zero(), unsigned as u8 == 0, "{}", context);
+                assert_eq!(cpu.negative(), unsigned as u8 >= 0x80, "{}", context);
+            }
+        }
+    }
+}
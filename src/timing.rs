@@ -0,0 +1,99 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// Throttling for front-ends that would otherwise run the emulator flat out
+//
+// Time is read through a Clock so the arithmetic can be tested without
+// actually sleeping. A frame is timed from whenever the caller says it
+// started, usually right before running a frame's worth of cycles
+
+// NTSC NES refresh rate
+pub const NTSC_FPS: f64 = 60.0988;
+
+pub trait Clock {
+    // time elapsed since some fixed but arbitrary point
+    fn now(&self) -> Duration;
+
+    fn sleep(&mut self, duration: Duration);
+}
+
+// The real wall clock
+pub struct SystemClock {
+    epoch: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+pub struct FrameLimiter<C: Clock> {
+    clock: C,
+    frame_duration: Duration,
+    // 2.0 is fast-forward at double speed, 0.5 slow motion
+    speed: f64,
+}
+
+impl FrameLimiter<SystemClock> {
+    // Real time playback at the NTSC frame rate
+    pub fn ntsc() -> Self {
+        FrameLimiter::new(SystemClock::new(), NTSC_FPS)
+    }
+}
+
+impl<C: Clock> FrameLimiter<C> {
+    pub fn new(clock: C, fps: f64) -> Self {
+        FrameLimiter {
+            clock,
+            frame_duration: Duration::from_secs_f64(1.0 / fps),
+            speed: 1.0,
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        assert!(speed > 0.0, "speed must be positive");
+        self.speed = speed;
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    // How long is left of the frame that began at frame_start, zero if it
+    // already overran
+    pub fn remaining(&self, frame_start: Duration) -> Duration {
+        let target = self.frame_duration.div_f64(self.speed);
+        let elapsed = self.clock.now().saturating_sub(frame_start);
+        target.saturating_sub(elapsed)
+    }
+
+    // Sleeps out the rest of the frame and returns for how long
+    pub fn wait(&mut self, frame_start: Duration) -> Duration {
+        let remaining = self.remaining(frame_start);
+        if !remaining.is_zero() {
+            self.clock.sleep(remaining);
+        }
+        remaining
+    }
+}